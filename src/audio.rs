@@ -1,7 +1,11 @@
+use std::collections::HashMap;
+
 use ggez::{audio::{self, SoundSource}, Context};
-use specs::{World, WorldExt};
 
-use crate::resources::AudioStore;
+#[derive(Default)]
+pub struct AudioStore {
+  pub sounds: HashMap<String, audio::Source>,
+}
 
 impl AudioStore {
   pub fn play_sound(&mut self, context: &mut Context,  sound_name: &str) {
@@ -11,9 +15,8 @@ impl AudioStore {
   }
 }
 
-pub fn initialize_sounds(world: &mut World, context: &mut Context) {
-  let mut audio_store = world.write_resource::<AudioStore>();
-  let sounds = ["correct", "incorrect", "wall"];
+pub fn initialize_sounds(audio_store: &mut AudioStore, context: &mut Context) {
+  let sounds = ["correct", "incorrect", "wall", "deadlock", "teleport", "key", "door", "gate"];
 
   for sound in sounds.iter() {
     let sound_name = sound.to_string();
@@ -22,4 +25,4 @@ pub fn initialize_sounds(world: &mut World, context: &mut Context) {
 
     audio_store.sounds.insert(sound_name, sound_source);
   }
-}
\ No newline at end of file
+}
@@ -0,0 +1,280 @@
+use std::{collections::{BTreeMap, HashMap}, fmt::{self, Display}, fs, io, path::Path, str::FromStr, time::Duration};
+
+use ggez::input::keyboard::KeyCode;
+use serde::Deserialize;
+
+use crate::{components::Direction, resources::{Command, RepeatSettings}};
+
+/*
+玩家可以绑定按键的操作
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+  MoveUp,
+  MoveDown,
+  MoveLeft,
+  MoveRight,
+  Undo,
+  Redo,
+  Restart,
+  Hint,
+  ZoomIn,
+  ZoomOut,
+  ResetZoom,
+  // Held down, turns moves into pulls
+  Pull,
+}
+
+impl Action {
+  // The game command behind the action, if it is one. Restarting, hints and
+  // the camera are handled by the front end.
+  pub fn command(self) -> Option<Command> {
+    match self {
+      Action::MoveUp => Some(Command::Move(Direction::Up)),
+      Action::MoveDown => Some(Command::Move(Direction::Down)),
+      Action::MoveLeft => Some(Command::Move(Direction::Left)),
+      Action::MoveRight => Some(Command::Move(Direction::Right)),
+      Action::Undo => Some(Command::Undo),
+      Action::Redo => Some(Command::Redo),
+      Action::Restart | Action::Hint | Action::ZoomIn | Action::ZoomOut | Action::ResetZoom | Action::Pull => None,
+    }
+  }
+}
+
+impl FromStr for Action {
+  type Err = BindingsError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "move_up" => Ok(Action::MoveUp),
+      "move_down" => Ok(Action::MoveDown),
+      "move_left" => Ok(Action::MoveLeft),
+      "move_right" => Ok(Action::MoveRight),
+      "undo" => Ok(Action::Undo),
+      "redo" => Ok(Action::Redo),
+      "restart" => Ok(Action::Restart),
+      "hint" => Ok(Action::Hint),
+      "zoom_in" => Ok(Action::ZoomIn),
+      "zoom_out" => Ok(Action::ZoomOut),
+      "reset_zoom" => Ok(Action::ResetZoom),
+      "pull" => Ok(Action::Pull),
+      _ => Err(BindingsError::UnknownAction(s.to_string())),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum BindingsError {
+  Io(io::Error),
+  Parse(toml::de::Error),
+  UnknownAction(String),
+  UnknownKey(String),
+  DuplicateKey { key: String, actions: [String; 2] },
+}
+
+impl Display for BindingsError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      BindingsError::Io(error) => write!(f, "could not read key bindings: {}", error),
+      BindingsError::Parse(error) => write!(f, "invalid key bindings: {}", error),
+      BindingsError::UnknownAction(action) => write!(f, "unknown action {:?} in key bindings", action),
+      BindingsError::UnknownKey(key) => write!(f, "unknown key {:?} in key bindings", key),
+      BindingsError::DuplicateKey { key, actions: [first, second] } => {
+        write!(f, "key {:?} is bound to both {} and {}", key, first, second)
+      }
+    }
+  }
+}
+
+impl std::error::Error for BindingsError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      BindingsError::Io(error) => Some(error),
+      BindingsError::Parse(error) => Some(error),
+      BindingsError::UnknownAction(_) | BindingsError::UnknownKey(_) | BindingsError::DuplicateKey { .. } => None,
+    }
+  }
+}
+
+impl From<io::Error> for BindingsError {
+  fn from(error: io::Error) -> Self {
+    BindingsError::Io(error)
+  }
+}
+
+// The layout of the settings file, e.g.
+//
+// [bindings]
+// move_up = ["Up", "W", "K"]
+// undo = ["Z", "Back"]
+//
+// [repeat]
+// delay_ms = 250
+// interval_ms = 100
+#[derive(Deserialize)]
+struct Settings {
+  #[serde(default)]
+  bindings: BTreeMap<String, Vec<String>>,
+  repeat: Option<RepeatTable>,
+}
+
+#[derive(Deserialize)]
+struct RepeatTable {
+  delay_ms: Option<u64>,
+  interval_ms: Option<u64>,
+}
+
+/*
+按键到操作的映射，没有在设置文件里出现的操作保留默认按键
+*/
+#[derive(Debug, Clone)]
+pub struct KeyBindings {
+  actions: HashMap<KeyCode, Action>,
+  // How held keys repeat
+  pub repeat: RepeatSettings,
+}
+
+impl Default for KeyBindings {
+  fn default() -> Self {
+    let actions = [
+      (KeyCode::Up, Action::MoveUp),
+      (KeyCode::Down, Action::MoveDown),
+      (KeyCode::Left, Action::MoveLeft),
+      (KeyCode::Right, Action::MoveRight),
+      (KeyCode::Z, Action::Undo),
+      (KeyCode::Back, Action::Undo),
+      (KeyCode::Y, Action::Redo),
+      (KeyCode::R, Action::Restart),
+      (KeyCode::H, Action::Hint),
+      (KeyCode::Equals, Action::ZoomIn),
+      (KeyCode::Plus, Action::ZoomIn),
+      (KeyCode::Minus, Action::ZoomOut),
+      (KeyCode::Key0, Action::ResetZoom),
+      (KeyCode::LShift, Action::Pull),
+      (KeyCode::RShift, Action::Pull),
+    ];
+
+    KeyBindings {
+      actions: actions.into_iter().collect(),
+      repeat: RepeatSettings::default(),
+    }
+  }
+}
+
+impl KeyBindings {
+  // Reads the bindings from a settings file, keeping the defaults when there
+  // is no such file
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, BindingsError> {
+    match fs::read_to_string(path) {
+      Ok(settings) => settings.parse(),
+      Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(KeyBindings::default()),
+      Err(error) => Err(error.into()),
+    }
+  }
+
+  pub fn action(&self, keycode: KeyCode) -> Option<Action> {
+    self.actions.get(&keycode).copied()
+  }
+}
+
+impl FromStr for KeyBindings {
+  type Err = BindingsError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let settings: Settings = toml::from_str(s).map_err(BindingsError::Parse)?;
+    let mut key_bindings = KeyBindings::default();
+    // Keys from the file take over from the defaults of other actions, but
+    // one key can't be given to two actions in the file
+    let mut bound_by: HashMap<KeyCode, &str> = HashMap::new();
+
+    for (name, keys) in settings.bindings.iter() {
+      let action: Action = name.parse()?;
+      key_bindings.actions.retain(|_, bound| *bound != action);
+      for key in keys {
+        let keycode = parse_key(key).ok_or_else(|| BindingsError::UnknownKey(key.clone()))?;
+        match bound_by.insert(keycode, name) {
+          Some(other) if other != name => {
+            return Err(BindingsError::DuplicateKey { key: key.clone(), actions: [other.to_string(), name.clone()] });
+          }
+          _ => (),
+        }
+        key_bindings.actions.insert(keycode, action);
+      }
+    }
+
+    if let Some(repeat) = settings.repeat {
+      if let Some(delay_ms) = repeat.delay_ms {
+        key_bindings.repeat.delay = Duration::from_millis(delay_ms);
+      }
+      if let Some(interval_ms) = repeat.interval_ms {
+        key_bindings.repeat.interval = Duration::from_millis(interval_ms);
+      }
+    }
+
+    Ok(key_bindings)
+  }
+}
+
+// Key names as ggez spells them: letters, digits as `Key1`, and a few others
+fn parse_key(name: &str) -> Option<KeyCode> {
+  let keycode = match name {
+    "A" => KeyCode::A,
+    "B" => KeyCode::B,
+    "C" => KeyCode::C,
+    "D" => KeyCode::D,
+    "E" => KeyCode::E,
+    "F" => KeyCode::F,
+    "G" => KeyCode::G,
+    "H" => KeyCode::H,
+    "I" => KeyCode::I,
+    "J" => KeyCode::J,
+    "K" => KeyCode::K,
+    "L" => KeyCode::L,
+    "M" => KeyCode::M,
+    "N" => KeyCode::N,
+    "O" => KeyCode::O,
+    "P" => KeyCode::P,
+    "Q" => KeyCode::Q,
+    "R" => KeyCode::R,
+    "S" => KeyCode::S,
+    "T" => KeyCode::T,
+    "U" => KeyCode::U,
+    "V" => KeyCode::V,
+    "W" => KeyCode::W,
+    "X" => KeyCode::X,
+    "Y" => KeyCode::Y,
+    "Z" => KeyCode::Z,
+    "Key0" => KeyCode::Key0,
+    "Key1" => KeyCode::Key1,
+    "Key2" => KeyCode::Key2,
+    "Key3" => KeyCode::Key3,
+    "Key4" => KeyCode::Key4,
+    "Key5" => KeyCode::Key5,
+    "Key6" => KeyCode::Key6,
+    "Key7" => KeyCode::Key7,
+    "Key8" => KeyCode::Key8,
+    "Key9" => KeyCode::Key9,
+    "Up" => KeyCode::Up,
+    "Down" => KeyCode::Down,
+    "Left" => KeyCode::Left,
+    "Right" => KeyCode::Right,
+    "Back" => KeyCode::Back,
+    "Delete" => KeyCode::Delete,
+    "Space" => KeyCode::Space,
+    "LShift" => KeyCode::LShift,
+    "RShift" => KeyCode::RShift,
+    "LControl" => KeyCode::LControl,
+    "RControl" => KeyCode::RControl,
+    "LAlt" => KeyCode::LAlt,
+    "RAlt" => KeyCode::RAlt,
+    "Return" => KeyCode::Return,
+    "Tab" => KeyCode::Tab,
+    "Escape" => KeyCode::Escape,
+    "Equals" => KeyCode::Equals,
+    "Plus" => KeyCode::Plus,
+    "Minus" => KeyCode::Minus,
+    _ => return None,
+  };
+
+  Some(keycode)
+}
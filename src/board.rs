@@ -0,0 +1,261 @@
+use std::{collections::VecDeque, fmt::{self, Display}};
+
+use specs::{Component, Join, ReadStorage, World, WorldExt};
+
+use crate::{components::{Box, BoxColor, BoxSpot, Direction, Door, Gate, Ice, Immovable, Player, Position, Teleporter}, maps::{Fixture, Level, Occupant, Terrain}, resources::MapBounds};
+
+pub const DIRECTIONS: [Direction; 4] = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+
+// Level features the solver doesn't model. Their cells count as walls, so
+// walks and pushes planned on the board keep clear of them, but solutions
+// that would need to cross them are never found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mechanic {
+  Ice,
+  Teleporters,
+  // Locked doors and gates, which open as the level is played
+  Locks,
+}
+
+impl Display for Mechanic {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Mechanic::Ice => write!(f, "ice"),
+      Mechanic::Teleporters => write!(f, "teleporters"),
+      Mechanic::Locks => write!(f, "doors and gates"),
+    }
+  }
+}
+
+/*
+静态棋盘：墙和箱子位置，不包括玩家和箱子
+*/
+#[derive(Debug, Clone)]
+pub struct Board {
+  width: usize,
+  height: usize,
+  // Anything outside of the level counts as a wall
+  walls: Vec<bool>,
+  spots: Vec<Option<BoxColor>>,
+  mechanics: Vec<Mechanic>,
+}
+
+// Where the player and the boxes stand on a board
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoardState {
+  pub player: usize,
+  pub boxes: Vec<(usize, BoxColor)>,
+}
+
+impl Board {
+  pub fn from_level(level: &Level) -> (Board, BoardState) {
+    let (width, height) = (level.width(), level.height());
+    let mut board = Board {
+      width,
+      height,
+      walls: vec![true; width * height],
+      spots: vec![None; width * height],
+      mechanics: Vec::new(),
+    };
+    let mut state = BoardState { player: 0, boxes: Vec::new() };
+
+    for y in 0..height {
+      for x in 0..width {
+        let index = y * width + x;
+        let cell = level.cell(x, y);
+
+        // Doors start locked and gates closed
+        let locked = matches!(cell.fixture, Some(Fixture::Door(_) | Fixture::Gate));
+        board.walls[index] = cell.terrain != Terrain::Floor || cell.teleporter.is_some() || locked;
+        board.spots[index] = cell.spot;
+        if cell.terrain == Terrain::Ice {
+          board.add_mechanic(Mechanic::Ice);
+        }
+        if cell.teleporter.is_some() {
+          board.add_mechanic(Mechanic::Teleporters);
+        }
+        if locked {
+          board.add_mechanic(Mechanic::Locks);
+        }
+        match cell.occupant {
+          Some(Occupant::Player) => state.player = index,
+          Some(Occupant::Box(color)) => state.boxes.push((index, color)),
+          None => (),
+        }
+      }
+    }
+
+    (board, state)
+  }
+
+  // Takes a snapshot of the level as it is being played
+  pub fn from_world(world: &World) -> (Board, BoardState) {
+    let positions = world.read_storage::<Position>();
+    let mut board = Board::from_storages(
+      &world.read_resource::<MapBounds>(),
+      &positions,
+      &world.read_storage::<Immovable>(),
+      &world.read_storage::<BoxSpot>(),
+    );
+    // Whatever steps on ice slides past where a walk would stop
+    for (position, _) in (&positions, &world.read_storage::<Ice>()).join() {
+      if let Some(cell) = board.index(position.x, position.y) {
+        board.walls[cell] = true;
+        board.add_mechanic(Mechanic::Ice);
+      }
+    }
+    // Teleporters send it somewhere else entirely
+    for (position, _) in (&positions, &world.read_storage::<Teleporter>()).join() {
+      if let Some(cell) = board.index(position.x, position.y) {
+        board.walls[cell] = true;
+        board.add_mechanic(Mechanic::Teleporters);
+      }
+    }
+    // Locked doors are already walls and opened ones are off the board. Open
+    // gates can close again, so they are walls too.
+    if (&positions, &world.read_storage::<Door>()).join().next().is_some() {
+      board.add_mechanic(Mechanic::Locks);
+    }
+    for (position, _) in (&positions, &world.read_storage::<Gate>()).join() {
+      if let Some(cell) = board.index(position.x, position.y) {
+        board.walls[cell] = true;
+        board.add_mechanic(Mechanic::Locks);
+      }
+    }
+    let mut state = BoardState { player: 0, boxes: Vec::new() };
+
+    for (position, the_box) in (&positions, &world.read_storage::<Box>()).join() {
+      if let Some(cell) = board.index(position.x, position.y) {
+        state.boxes.push((cell, the_box.color));
+      }
+    }
+    if let Some(cell) = (&positions, &world.read_storage::<Player>()).join().find_map(|(position, _)| board.index(position.x, position.y)) {
+      state.player = cell;
+    }
+
+    (board, state)
+  }
+
+  // The walls and spots of the level being played, for systems that can't
+  // borrow the whole world. Whatever has a `W` component counts as a wall.
+  pub fn from_storages<W: Component>(
+    map_bounds: &MapBounds,
+    positions: &ReadStorage<Position>,
+    walls: &ReadStorage<W>,
+    box_spots: &ReadStorage<BoxSpot>,
+  ) -> Board {
+    let (width, height) = (map_bounds.width.max(0) as usize, map_bounds.height.max(0) as usize);
+    let mut board = Board {
+      width,
+      height,
+      walls: vec![false; width * height],
+      spots: vec![None; width * height],
+      mechanics: Vec::new(),
+    };
+
+    for (position, _) in (positions, walls).join() {
+      if let Some(cell) = board.index(position.x, position.y) {
+        board.walls[cell] = true;
+      }
+    }
+    for (position, spot) in (positions, box_spots).join() {
+      if let Some(cell) = board.index(position.x, position.y) {
+        board.spots[cell] = Some(spot.color);
+      }
+    }
+
+    board
+  }
+
+  pub fn has(&self, mechanic: Mechanic) -> bool {
+    self.mechanics.contains(&mechanic)
+  }
+
+  fn add_mechanic(&mut self, mechanic: Mechanic) {
+    if !self.mechanics.contains(&mechanic) {
+      self.mechanics.push(mechanic);
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.walls.len()
+  }
+
+  pub fn index(&self, x: i32, y: i32) -> Option<usize> {
+    if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+      Some(y as usize * self.width + x as usize)
+    } else {
+      None
+    }
+  }
+
+  pub fn coords(&self, index: usize) -> (i32, i32) {
+    ((index % self.width) as i32, (index / self.width) as i32)
+  }
+
+  pub fn is_wall(&self, index: usize) -> bool {
+    self.walls[index]
+  }
+
+  pub fn spot(&self, index: usize) -> Option<BoxColor> {
+    self.spots[index]
+  }
+
+  // The cell next to `index` in the given direction, if it is on the board
+  pub fn neighbor(&self, index: usize, direction: Direction) -> Option<usize> {
+    let (x, y) = self.coords(index);
+    let (dx, dy) = direction.delta();
+    self.index(x + dx, y + dy)
+  }
+
+  // Like `neighbor`, but only for cells that aren't walls
+  pub fn floor_neighbor(&self, index: usize, direction: Direction) -> Option<usize> {
+    self.neighbor(index, direction).filter(|next| !self.walls[*next])
+  }
+
+  // Every spot of the given color
+  pub fn spots_of(&self, color: BoxColor) -> Vec<usize> {
+    (0..self.len()).filter(|index| !self.walls[*index] && self.spots[*index] == Some(color)).collect()
+  }
+
+  // For every cell, the least number of pushes needed to get a box of the
+  // given color from there onto one of its spots, ignoring the other boxes.
+  // Cells a box can never leave for a spot are `None`: these are dead squares.
+  pub fn push_distances(&self, color: BoxColor) -> Vec<Option<u32>> {
+    self.pull_distances(&self.spots_of(color))
+  }
+
+  // Same as `push_distances`, towards a single target cell
+  pub fn push_distances_to(&self, target: usize) -> Vec<Option<u32>> {
+    self.pull_distances(&[target])
+  }
+
+  fn pull_distances(&self, targets: &[usize]) -> Vec<Option<u32>> {
+    let mut distances = vec![None; self.len()];
+    let mut queue = VecDeque::new();
+
+    for target in targets {
+      distances[*target] = Some(0);
+      queue.push_back(*target);
+    }
+
+    // Walk backwards by pulling: a box on `index` could have been pushed there
+    // from `from` if the player had room to stand behind it.
+    while let Some(index) = queue.pop_front() {
+      let distance = distances[index].unwrap_or(0);
+      for direction in DIRECTIONS {
+        let from = match self.floor_neighbor(index, direction) {
+          Some(from) => from,
+          None => continue,
+        };
+        if self.floor_neighbor(from, direction).is_none() || distances[from].is_some() {
+          continue;
+        }
+        distances[from] = Some(distance + 1);
+        queue.push_back(from);
+      }
+    }
+
+    distances
+  }
+}
@@ -0,0 +1,149 @@
+use crate::constants::TILE_WIDTH;
+
+// Room kept to the right of the board for the HUD
+const HUD_WIDTH: f32 = 180.0;
+const MARGIN: f32 = 16.0;
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 4.0;
+
+/*
+摄像机：把关卡缩放并居中到窗口里，支持缩放和拖动，HUD 跟着棋盘走
+*/
+#[derive(Debug, Clone)]
+pub struct Camera {
+  // Size of the window in pixels
+  viewport: (f32, f32),
+  // Size of the level in world pixels, a tile being TILE_WIDTH wide
+  board: (f32, f32),
+  // On top of the scale that fits the level in the window
+  zoom: f32,
+  // The world point shown in the middle of the board area, once the board
+  // is too big to be shown whole
+  center: (f32, f32),
+}
+
+impl Camera {
+  pub fn new(viewport_width: f32, viewport_height: f32) -> Self {
+    Camera {
+      viewport: (viewport_width, viewport_height),
+      board: (TILE_WIDTH, TILE_WIDTH),
+      zoom: 1.0,
+      center: (0.0, 0.0),
+    }
+  }
+
+  pub fn resize(&mut self, viewport_width: f32, viewport_height: f32) {
+    self.viewport = (viewport_width, viewport_height);
+  }
+
+  // Shows a whole level of the given size in tiles
+  pub fn fit_level(&mut self, width: i32, height: i32) {
+    self.board = (width.max(1) as f32 * TILE_WIDTH, height.max(1) as f32 * TILE_WIDTH);
+    self.zoom = 1.0;
+    self.center = (self.board.0 / 2.0, self.board.1 / 2.0);
+  }
+
+  pub fn zoom_by(&mut self, factor: f32) {
+    self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+    self.center = self.clamped_center();
+  }
+
+  pub fn reset_zoom(&mut self) {
+    self.zoom = 1.0;
+    self.center = self.clamped_center();
+  }
+
+  // Moves the view along with a drag of the given number of pixels
+  pub fn scroll(&mut self, dx: f32, dy: f32) {
+    let scale = self.scale();
+    self.center = (self.center.0 - dx / scale, self.center.1 - dy / scale);
+    self.center = self.clamped_center();
+  }
+
+  // Scrolls just enough for the given tile to be on screen, with a tile to
+  // spare around it
+  pub fn keep_in_view(&mut self, x: i32, y: i32) {
+    let (_, _, area_width, area_height) = self.board_area();
+    let scale = self.scale();
+    let (half_width, half_height) = (area_width / 2.0 / scale, area_height / 2.0 / scale);
+
+    let (left, top) = (x as f32 * TILE_WIDTH - TILE_WIDTH, y as f32 * TILE_WIDTH - TILE_WIDTH);
+    let (right, bottom) = (left + 3.0 * TILE_WIDTH, top + 3.0 * TILE_WIDTH);
+
+    self.center.0 = self.center.0.min(left + half_width).max(right - half_width);
+    self.center.1 = self.center.1.min(top + half_height).max(bottom - half_height);
+    self.center = self.clamped_center();
+  }
+
+  // Screen pixels per world pixel
+  pub fn scale(&self) -> f32 {
+    let (_, _, area_width, area_height) = self.board_area();
+    let fit = (area_width / self.board.0).min(area_height / self.board.1);
+    fit * self.zoom
+  }
+
+  // The part of the world seen through the window, as x, y, width and height
+  pub fn world_rect(&self) -> (f32, f32, f32, f32) {
+    let scale = self.scale();
+    let (offset_x, offset_y) = self.offset();
+    (-offset_x / scale, -offset_y / scale, self.viewport.0 / scale, self.viewport.1 / scale)
+  }
+
+  // The tile under a point of the window
+  pub fn tile_at(&self, x: f32, y: f32) -> (i32, i32) {
+    let scale = self.scale();
+    let (offset_x, offset_y) = self.offset();
+    let (world_x, world_y) = ((x - offset_x) / scale, (y - offset_y) / scale);
+    ((world_x / TILE_WIDTH).floor() as i32, (world_y / TILE_WIDTH).floor() as i32)
+  }
+
+  // Where the HUD starts: right of the board, but never off the window
+  pub fn hud_position(&self) -> (f32, f32) {
+    let (offset_x, offset_y) = self.offset();
+    let board_right = offset_x + self.board.0 * self.scale();
+    ((board_right + MARGIN).min(self.viewport.0 - HUD_WIDTH + MARGIN), offset_y.max(MARGIN))
+  }
+
+  // Everything but the HUD and the margins
+  fn board_area(&self) -> (f32, f32, f32, f32) {
+    let width = (self.viewport.0 - HUD_WIDTH - 2.0 * MARGIN).max(1.0);
+    let height = (self.viewport.1 - 2.0 * MARGIN).max(1.0);
+    (MARGIN, MARGIN, width, height)
+  }
+
+  // Where the world origin is on screen. A board that fits is centered in
+  // its area, a bigger one follows `center`.
+  fn offset(&self) -> (f32, f32) {
+    let (area_x, area_y, area_width, area_height) = self.board_area();
+    let scale = self.scale();
+    let center = self.clamped_center();
+
+    let axis = |area_start: f32, area_length: f32, board_length: f32, center: f32| {
+      let length = board_length * scale;
+      if length <= area_length {
+        area_start + (area_length - length) / 2.0
+      } else {
+        area_start + area_length / 2.0 - center * scale
+      }
+    };
+
+    (axis(area_x, area_width, self.board.0, center.0), axis(area_y, area_height, self.board.1, center.1))
+  }
+
+  // Keeps the board from being scrolled away from its area
+  fn clamped_center(&self) -> (f32, f32) {
+    let (_, _, area_width, area_height) = self.board_area();
+    let scale = self.scale();
+
+    let clamp = |center: f32, area_length: f32, board_length: f32| {
+      let half = area_length / 2.0 / scale;
+      if board_length <= 2.0 * half {
+        board_length / 2.0
+      } else {
+        center.clamp(half, board_length - half)
+      }
+    };
+
+    (clamp(self.center.0, area_width, self.board.0), clamp(self.center.1, area_height, self.board.1))
+  }
+}
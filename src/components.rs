@@ -1,4 +1,4 @@
-use std::fmt::Display;
+use std::{fmt::Display, time::Duration};
 
 use specs::{Component, NullStorage, VecStorage, World, WorldExt};
 
@@ -8,11 +8,40 @@ use specs::{Component, NullStorage, VecStorage, World, WorldExt};
 #[derive(Component, Debug, Copy, Clone)]
 #[storage(VecStorage)]
 pub struct Position {
-  pub x: u8,
-  pub y: u8,
+  pub x: i32,
+  pub y: i32,
   pub z: u8,
 }
 
+/*
+画面上的位置，在两个格子之间平滑移动，游戏逻辑只看 Position
+ */
+#[derive(Component, Debug, Copy, Clone)]
+#[storage(VecStorage)]
+pub struct VisualPosition {
+  pub x: f32,
+  pub y: f32,
+  // The tween runs from `from` to the cell `to`, starting at `started_at`
+  pub from: (f32, f32),
+  pub to: (i32, i32),
+  pub started_at: Duration,
+}
+
+impl VisualPosition {
+  // Standing still on the given position
+  pub fn at(position: Position) -> Self {
+    let (x, y) = (position.x as f32, position.y as f32);
+    VisualPosition { x, y, from: (x, y), to: (position.x, position.y), started_at: Duration::ZERO }
+  }
+
+  pub fn snap(&mut self) {
+    let (x, y) = (self.to.0 as f32, self.to.1 as f32);
+    self.x = x;
+    self.y = y;
+    self.from = (x, y);
+  }
+}
+
 /*
 渲染组件
  */
@@ -66,7 +95,59 @@ pub struct Wall {}
 */
 #[derive(Component)]
 #[storage(VecStorage)]
-pub struct Player {}
+pub struct Player {
+  pub facing: Direction,
+  // What the last step was and when it was taken
+  pub step: PlayerState,
+  pub stepped_at: Duration,
+}
+
+impl Default for Player {
+  fn default() -> Self {
+    Player {
+      facing: Direction::Down,
+      step: PlayerState::Idle,
+      stepped_at: Duration::ZERO,
+    }
+  }
+}
+
+impl Player {
+  // How long the player keeps walking or pushing after a step
+  const STEP_POSE: Duration = Duration::from_millis(200);
+
+  pub fn take_step(&mut self, direction: Direction, step: PlayerState, now: Duration) {
+    self.facing = direction;
+    self.step = step;
+    self.stepped_at = now;
+  }
+
+  // What the player is doing at the given time
+  pub fn state(&self, now: Duration) -> PlayerState {
+    if now.saturating_sub(self.stepped_at) < Player::STEP_POSE {
+      self.step
+    } else {
+      PlayerState::Idle
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerState {
+  Idle,
+  Walking,
+  Pushing,
+}
+
+impl Display for PlayerState {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      PlayerState::Idle => write!(f, "idle"),
+      PlayerState::Walking => write!(f, "walk"),
+      PlayerState::Pushing => write!(f, "push"),
+    }
+  }
+}
 
 /*
 箱子组件
@@ -86,7 +167,7 @@ pub struct BoxSpot {
   pub color: BoxColor,
 }
 
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum BoxColor {
   Blue,
   Red
@@ -101,6 +182,58 @@ impl Display for BoxColor {
   }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+  Up,
+  Down,
+  Left,
+  Right,
+}
+
+impl Display for Direction {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Direction::Up => write!(f, "up"),
+      Direction::Down => write!(f, "down"),
+      Direction::Left => write!(f, "left"),
+      Direction::Right => write!(f, "right"),
+    }
+  }
+}
+
+impl Direction {
+  // The change in (x, y) of one step in this direction
+  pub fn delta(self) -> (i32, i32) {
+    match self {
+      Direction::Up => (0, -1),
+      Direction::Down => (0, 1),
+      Direction::Left => (-1, 0),
+      Direction::Right => (1, 0),
+    }
+  }
+
+  // Walks and pushes are replayed the same way, so the letter case is ignored
+  pub fn from_lurd(step: char) -> Option<Direction> {
+    match step.to_ascii_lowercase() {
+      'u' => Some(Direction::Up),
+      'd' => Some(Direction::Down),
+      'l' => Some(Direction::Left),
+      'r' => Some(Direction::Right),
+      _ => None,
+    }
+  }
+
+  // The lowercase LURD letter of this direction, used for walking steps
+  pub fn lurd(self) -> char {
+    match self {
+      Direction::Up => 'u',
+      Direction::Down => 'd',
+      Direction::Left => 'l',
+      Direction::Right => 'r',
+    }
+  }
+}
+
 #[derive(Component, Default)]
 #[storage(NullStorage)]
 pub struct Movable;
@@ -109,8 +242,75 @@ pub struct Movable;
 #[storage(NullStorage)]
 pub struct Immovable;
 
+/*
+卡死的箱子，渲染时会被标出来
+*/
+#[derive(Component, Default)]
+#[storage(NullStorage)]
+pub struct Stuck;
+
+/*
+冰面：走上来的玩家和推上来的箱子会一直滑到撞上障碍为止
+*/
+#[derive(Component, Default)]
+#[storage(NullStorage)]
+pub struct Ice;
+
+/*
+传送门：走进或者推进来的东西会被送到同一频道的另一个传送门
+*/
+#[derive(Component)]
+#[storage(VecStorage)]
+pub struct Teleporter {
+  pub channel: u8,
+}
+
+/*
+钥匙：玩家走上去就捡起来，可以打开同色的门
+*/
+#[derive(Component)]
+#[storage(VecStorage)]
+pub struct Key {
+  pub color: BoxColor,
+}
+
+/*
+上锁的门：带着同色钥匙的玩家走过去就会打开
+*/
+#[derive(Component)]
+#[storage(VecStorage)]
+pub struct Door {
+  pub color: BoxColor,
+}
+
+/*
+压力板：有箱子或者玩家站在上面时算是按下
+*/
+#[derive(Component, Default)]
+#[storage(VecStorage)]
+pub struct Plate {
+  pub pressed: bool,
+}
+
+/*
+栅门：所有压力板都按下时打开
+*/
+#[derive(Component, Default)]
+#[storage(VecStorage)]
+pub struct Gate {
+  pub open: bool,
+}
+
+impl Gate {
+  pub fn image_path(&self) -> String {
+    let state = if self.open { "open" } else { "closed" };
+    format!("/images/gate_{}.png", state)
+  }
+}
+
 pub fn register_components(world: &mut World) {
   world.register::<Position>();
+  world.register::<VisualPosition>();
   world.register::<Renderable>();
   world.register::<Wall>();
   world.register::<Player>();
@@ -118,4 +318,11 @@ pub fn register_components(world: &mut World) {
   world.register::<BoxSpot>();
   world.register::<Movable>();
   world.register::<Immovable>();
+  world.register::<Stuck>();
+  world.register::<Ice>();
+  world.register::<Teleporter>();
+  world.register::<Key>();
+  world.register::<Door>();
+  world.register::<Plate>();
+  world.register::<Gate>();
 }
\ No newline at end of file
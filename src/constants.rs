@@ -1,4 +1 @@
-pub const MAP_WIDTH: u8 = 8;
-pub const MAP_HEIGHT: u8 = 9;
-
 pub const TILE_WIDTH: f32 = 32.0;
\ No newline at end of file
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use crate::{board::Board, components::{BoxColor, Direction}, events::DeadlockKind, resources::RuleSet};
+
+/*
+死锁检测：箱子被推进死角、死格或者互相卡住以后，这一关就解不出来了
+*/
+pub struct DeadlockDetector<'a> {
+  board: &'a Board,
+  // The color of the box on every cell, if there is one
+  occupied: Vec<Option<BoxColor>>,
+  // The cells a box of each color can never be pushed to a spot from. Left
+  // empty when teleporters can take boxes where pushing can't.
+  dead_squares: HashMap<BoxColor, Vec<bool>>,
+  // Boxes side by side only freeze each other when pushed one at a time,
+  // chain pushes can move them together
+  single_pushes: bool,
+  // Any box can be pulled back out of a corner, so nothing is ever stuck
+  pulls: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Axis {
+  Horizontal,
+  Vertical,
+}
+
+impl Axis {
+  fn directions(self) -> [Direction; 2] {
+    match self {
+      Axis::Horizontal => [Direction::Left, Direction::Right],
+      Axis::Vertical => [Direction::Up, Direction::Down],
+    }
+  }
+
+  fn other(self) -> Axis {
+    match self {
+      Axis::Horizontal => Axis::Vertical,
+      Axis::Vertical => Axis::Horizontal,
+    }
+  }
+}
+
+impl<'a> DeadlockDetector<'a> {
+  pub fn new(board: &'a Board, boxes: &[(usize, BoxColor)], rules: RuleSet, teleporters: bool) -> Self {
+    let mut occupied = vec![None; board.len()];
+    let mut dead_squares = HashMap::new();
+
+    for (cell, color) in boxes {
+      occupied[*cell] = Some(*color);
+      if !teleporters {
+        dead_squares.entry(*color).or_insert_with(|| {
+          board.push_distances(*color).iter().map(|distance| distance.is_none()).collect()
+        });
+      }
+    }
+
+    DeadlockDetector { board, occupied, dead_squares, single_pushes: rules.push.max_boxes() == Some(1), pulls: rules.pull }
+  }
+
+  // Whether the box on `cell` can no longer reach a spot, and why. A box
+  // already on a spot of its color is only a deadlock when it freezes others.
+  pub fn check(&self, cell: usize) -> Option<DeadlockKind> {
+    if self.pulls {
+      return None;
+    }
+
+    let color = self.occupied[cell]?;
+    let misplaced = self.board.spot(cell) != Some(color);
+
+    if misplaced && self.is_corner(cell) {
+      return Some(DeadlockKind::Corner);
+    }
+    if misplaced && self.is_dead_square(cell, color) {
+      return Some(DeadlockKind::DeadSquare);
+    }
+
+    if !self.single_pushes {
+      return None;
+    }
+
+    // Boxes treated as walls while the frozen ones are looked for
+    let mut blocked = vec![false; self.board.len()];
+    let mut frozen = vec![cell];
+    let is_frozen = self.is_frozen_on(cell, Axis::Horizontal, &mut blocked, &mut frozen)
+      && self.is_frozen_on(cell, Axis::Vertical, &mut blocked, &mut frozen);
+    let any_misplaced = frozen.iter().any(|frozen_cell| self.board.spot(*frozen_cell) != self.occupied[*frozen_cell]);
+
+    if is_frozen && any_misplaced {
+      Some(DeadlockKind::Freeze)
+    } else {
+      None
+    }
+  }
+
+  fn is_wall(&self, cell: Option<usize>) -> bool {
+    cell.is_none_or(|cell| self.board.is_wall(cell))
+  }
+
+  fn is_dead_square(&self, cell: usize, color: BoxColor) -> bool {
+    self.dead_squares.get(&color).is_some_and(|dead| dead[cell])
+  }
+
+  // Walled in on one side of both axes
+  fn is_corner(&self, cell: usize) -> bool {
+    [Axis::Horizontal, Axis::Vertical]
+      .iter()
+      .all(|axis| axis.directions().iter().any(|direction| self.is_wall(self.board.neighbor(cell, *direction))))
+  }
+
+  // A box can't move along an axis when a wall is on either side, when both
+  // sides are dead squares, or when a box beside it can't move along the
+  // other axis. Every box found frozen on the way is added to `frozen`.
+  fn is_frozen_on(&self, cell: usize, axis: Axis, blocked: &mut Vec<bool>, frozen: &mut Vec<usize>) -> bool {
+    let sides = axis.directions().map(|direction| self.board.neighbor(cell, direction));
+    if sides.iter().any(|side| self.is_wall(*side) || side.is_some_and(|side| blocked[side])) {
+      return true;
+    }
+
+    let color = match self.occupied[cell] {
+      Some(color) => color,
+      None => return false,
+    };
+    if sides.iter().flatten().all(|side| self.is_dead_square(*side, color)) {
+      return true;
+    }
+
+    blocked[cell] = true;
+    for side in sides.iter().flatten() {
+      if self.occupied[*side].is_some() && self.is_frozen_on(*side, axis.other(), blocked, frozen) {
+        frozen.push(*side);
+        return true;
+      }
+    }
+    blocked[cell] = false;
+
+    false
+  }
+}
@@ -1,6 +1,6 @@
 use specs::{Builder, World, WorldExt};
 
-use crate::components::{Box, BoxColor, BoxSpot, Immovable, Movable, Player, Position, Renderable, Wall};
+use crate::components::{Box, BoxColor, BoxSpot, Door, Gate, Ice, Immovable, Key, Plate, Movable, Player, Position, Renderable, Teleporter, VisualPosition, Wall};
 
 /*
 创建墙实体
@@ -24,18 +24,84 @@ pub fn create_floor(world: &mut World, position: Position) {
       .build();
 }
 
+/*
+创建冰面实体，代替地面
+*/
+pub fn create_ice(world: &mut World, position: Position) {
+  world.create_entity()
+      .with(Position { z: 5, ..position })
+      .with(Renderable::new_static("/images/ice.png".to_string()))
+      .with(Ice)
+      .build();
+}
+
+/*
+创建传送门实体
+*/
+pub fn create_teleporter(world: &mut World, position: Position, channel: u8) {
+  world.create_entity()
+   .with(Position { z: 6, ..position })
+   .with(Renderable::new_static("/images/teleporter.png".to_string()))
+   .with(Teleporter { channel })
+   .build();
+}
+
+/*
+创建钥匙实体
+*/
+pub fn create_key(world: &mut World, position: Position, color: BoxColor) {
+  world.create_entity()
+   .with(Position { z: 9, ..position })
+   .with(Renderable::new_static(format!("/images/key_{}.png", color)))
+   .with(Key { color })
+   .build();
+}
+
+/*
+创建上锁的门实体
+*/
+pub fn create_door(world: &mut World, position: Position, color: BoxColor) {
+  world.create_entity()
+   .with(Position { z: 10, ..position })
+   .with(Renderable::new_static(format!("/images/door_{}.png", color)))
+   .with(Immovable {})
+   .with(Door { color })
+   .build();
+}
+
+/*
+创建压力板实体
+*/
+pub fn create_plate(world: &mut World, position: Position) {
+  world.create_entity()
+   .with(Position { z: 6, ..position })
+   .with(Renderable::new_static("/images/plate.png".to_string()))
+   .with(Plate::default())
+   .build();
+}
+
+/*
+创建栅门实体，一开始是关着的
+*/
+pub fn create_gate(world: &mut World, position: Position) {
+  let gate = Gate::default();
+  world.create_entity()
+   .with(Position { z: 8, ..position })
+   .with(Renderable::new_static(gate.image_path()))
+   .with(Immovable {})
+   .with(gate)
+   .build();
+}
+
 /*
 创建玩家实体
 */
 pub fn create_player(world: &mut World, position: Position) {
   world.create_entity()
    .with(Position { z: 10, ..position })
-   .with(Renderable:: new(vec![
-      "/images/player_1.png".to_string(), 
-      "/images/player_2.png".to_string(), 
-      "/images/player_3.png".to_string()]))
+   .with(VisualPosition::at(position))
    .with(Movable {})
-   .with(Player {})
+   .with(Player::default())
    .build();
 }
 
@@ -45,6 +111,7 @@ pub fn create_player(world: &mut World, position: Position) {
 pub fn create_box(world: &mut World, position: Position, box_color: BoxColor) {
   world.create_entity()
    .with(Position { z: 10, ..position })
+   .with(VisualPosition::at(position))
    .with(Renderable::new(vec![
       format!("/images/box_{}_1.png", box_color),
       format!("/images/box_{}_2.png", box_color)
@@ -1,4 +1,6 @@
-#[derive(Debug)]
+use crate::components::BoxColor;
+
+#[derive(Debug, Clone)]
 pub enum Event {
   // Fired when the player hits an obstacle like a wall
   PlayerHitObstacle,
@@ -10,16 +12,90 @@ pub enum Event {
   BoxPlacedOnSpot(BoxPlacedOnSpot),
 
   GameOver,
+
+  // Fired when a replayed solution runs out or wins the level
+  ReplayFinished(ReplayFinished),
+
+  // Fired when a box is moved somewhere it can never be solved from
+  Deadlock(Deadlock),
+
+  // Fired when an entity goes through a teleporter, after its EntityMoved
+  Teleported(Teleported),
+
+  // Fired when the player picks up a key
+  KeyPickedUp(KeyPickedUp),
+
+  // Fired when the player opens a locked door with a key
+  DoorUnlocked(DoorUnlocked),
+
+  // Fired when something steps on a pressure plate or leaves it
+  PlateChanged(PlateChanged),
+
+  // Fired when a gate opens or closes
+  GateChanged(GateChanged),
 }
 
 pub type EntityId = u32;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EntityMoved {
   pub id: EntityId,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BoxPlacedOnSpot {
   pub is_correct_spot: bool,
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone)]
+pub struct Deadlock {
+  pub id: EntityId,
+  pub kind: DeadlockKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeadlockKind {
+  // Pushed into a corner that isn't one of its spots
+  Corner,
+  // Pushed onto a square it can never be pushed to a spot from
+  DeadSquare,
+  // Stuck against walls and other boxes that can't move either
+  Freeze,
+}
+
+#[derive(Debug, Clone)]
+pub struct Teleported {
+  pub id: EntityId,
+  // From the tile the step started on to the exit it came out of
+  pub from: (i32, i32),
+  pub to: (i32, i32),
+}
+
+#[derive(Debug, Clone)]
+pub struct KeyPickedUp {
+  pub id: EntityId,
+  pub color: BoxColor,
+}
+
+#[derive(Debug, Clone)]
+pub struct DoorUnlocked {
+  pub id: EntityId,
+  pub color: BoxColor,
+}
+
+#[derive(Debug, Clone)]
+pub struct PlateChanged {
+  pub id: EntityId,
+  pub pressed: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct GateChanged {
+  pub id: EntityId,
+  pub open: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplayFinished {
+  pub solved: bool,
+}
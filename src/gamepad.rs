@@ -0,0 +1,99 @@
+use ggez::event::{Axis, Button};
+
+use crate::bindings::Action;
+
+#[derive(Debug, Clone, Copy)]
+pub struct GamepadSettings {
+  // How far the stick has to be pushed before it counts, from 0 to 1
+  pub deadzone: f32,
+}
+
+impl Default for GamepadSettings {
+  fn default() -> Self {
+    GamepadSettings { deadzone: 0.5 }
+  }
+}
+
+// What a stick movement did: it may have let go of one direction and
+// pressed another
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StickChange {
+  pub released: Option<Action>,
+  pub pressed: Option<Action>,
+}
+
+/*
+手柄输入：十字键、左摇杆和按键转换为操作。按住时的重复和键盘一样交给游戏核心
+*/
+#[derive(Debug, Default)]
+pub struct GamepadInput {
+  pub settings: GamepadSettings,
+  stick_x: f32,
+  stick_y: f32,
+  stick_action: Option<Action>,
+}
+
+impl GamepadInput {
+  // The action a button holds down while pressed
+  pub fn button_down(&self, button: Button) -> Option<Action> {
+    button_action(button)
+  }
+
+  pub fn button_up(&self, button: Button) -> Option<Action> {
+    button_action(button)
+  }
+
+  // The stick counts as a held direction from when it leaves the deadzone
+  // until it goes back or turns to another direction
+  pub fn axis(&mut self, axis: Axis, value: f32) -> StickChange {
+    match axis {
+      Axis::LeftStickX => self.stick_x = value,
+      Axis::LeftStickY => self.stick_y = value,
+      _ => return StickChange::default(),
+    }
+
+    let action = self.stick_direction();
+    if action == self.stick_action {
+      return StickChange::default();
+    }
+
+    let released = std::mem::replace(&mut self.stick_action, action);
+    StickChange { released, pressed: action }
+  }
+
+  // The stick only moves along its strongest axis, so diagonals don't flicker
+  // between two directions
+  fn stick_direction(&self) -> Option<Action> {
+    let (x, y) = (self.stick_x, self.stick_y);
+    if x.abs().max(y.abs()) < self.settings.deadzone {
+      return None;
+    }
+
+    let action = if x.abs() > y.abs() {
+      if x > 0.0 { Action::MoveRight } else { Action::MoveLeft }
+    } else if y > 0.0 {
+      Action::MoveUp
+    } else {
+      Action::MoveDown
+    };
+    Some(action)
+  }
+}
+
+fn button_action(button: Button) -> Option<Action> {
+  let action = match button {
+    Button::DPadUp => Action::MoveUp,
+    Button::DPadDown => Action::MoveDown,
+    Button::DPadLeft => Action::MoveLeft,
+    Button::DPadRight => Action::MoveRight,
+    Button::East => Action::Undo,
+    Button::West => Action::Redo,
+    Button::North => Action::Hint,
+    Button::Select => Action::Restart,
+    Button::LeftTrigger => Action::Pull,
+    _ => return None,
+  };
+
+  Some(action)
+}
+
@@ -0,0 +1,143 @@
+use std::{fs, path::Path, str::FromStr};
+
+use crate::{maps::{Level, LevelError, LevelMetadata}, resources::PushRule};
+
+/*
+关卡包
+*/
+#[derive(Debug, Clone, Default)]
+pub struct LevelPack {
+  levels: Vec<Level>,
+}
+
+impl LevelPack {
+  pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, LevelError> {
+    fs::read_to_string(path)?.parse()
+  }
+
+  pub fn len(&self) -> usize {
+    self.levels.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.levels.is_empty()
+  }
+
+  pub fn level(&self, index: usize) -> Option<&Level> {
+    self.levels.get(index)
+  }
+
+  pub fn levels(&self) -> &[Level] {
+    &self.levels
+  }
+}
+
+// Splits a `Key: value` header line, e.g. `Title: Microban 1`
+fn parse_header(line: &str) -> Option<(String, String)> {
+  let (key, value) = line.split_once(':')?;
+  let key = key.trim();
+  if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+    return None;
+  }
+  Some((key.to_ascii_lowercase(), value.trim().to_string()))
+}
+
+// `classic`, `unlimited` or `chain N`, where N is how many boxes can be
+// pushed at once
+fn parse_push_rule(value: &str) -> Option<PushRule> {
+  let mut words = value.split_whitespace().map(|word| word.to_ascii_lowercase());
+  let rule = match words.next()?.as_str() {
+    "classic" => PushRule::Classic,
+    "unlimited" => PushRule::Unlimited,
+    "chain" => match words.next()?.parse() {
+      Ok(strength) if strength > 0 => PushRule::Chain { strength },
+      _ => return None,
+    },
+    _ => return None,
+  };
+
+  match words.next() {
+    Some(_) => None,
+    None => Some(rule),
+  }
+}
+
+fn parse_switch(value: &str) -> Option<bool> {
+  match value.to_ascii_lowercase().as_str() {
+    "yes" | "on" | "true" => Some(true),
+    "no" | "off" | "false" => Some(false),
+    _ => None,
+  }
+}
+
+fn apply_header(metadata: &mut LevelMetadata, line: usize, key: &str, value: String) -> Result<(), LevelError> {
+  match key {
+    "title" => metadata.title = Some(value),
+    "author" => metadata.author = Some(value),
+    "comment" => metadata.comment = Some(value),
+    "rules" => metadata.rules.push = parse_push_rule(&value).ok_or(LevelError::UnknownRule { line, rule: value })?,
+    "pull" => metadata.rules.pull = parse_switch(&value).ok_or(LevelError::UnknownRule { line, rule: value })?,
+    // Unknown headers are allowed in pack files, we just don't use them
+    _ => (),
+  }
+  Ok(())
+}
+
+// Parses the collected map lines into a level that takes the pending headers
+fn push_level(levels: &mut Vec<Level>, map_lines: &mut Vec<(usize, &str)>, pending: &mut LevelMetadata) -> Result<(), LevelError> {
+  let mut level = Level::parse_lines(map_lines).map_err(|error| LevelError::InLevel {
+    level: levels.len() + 1,
+    error: Box::new(error),
+  })?;
+  level.metadata = std::mem::take(pending);
+  levels.push(level);
+  map_lines.clear();
+  Ok(())
+}
+
+impl FromStr for LevelPack {
+  type Err = LevelError;
+
+  // Levels are separated by anything that isn't a map line. Headers written
+  // before a map belong to that map, and so do headers that directly follow
+  // it without a blank line in between, since both layouts are common.
+  fn from_str(source: &str) -> Result<Self, Self::Err> {
+    let mut levels = Vec::new();
+    let mut map_lines = Vec::new();
+    let mut pending = LevelMetadata::default();
+    let mut trailing_headers = false;
+
+    for (index, line) in source.lines().enumerate() {
+      let line = line.trim_end();
+
+      if Level::is_map_line(line) {
+        map_lines.push((index + 1, line));
+        continue;
+      }
+
+      if !map_lines.is_empty() {
+        push_level(&mut levels, &mut map_lines, &mut pending)?;
+        trailing_headers = true;
+      }
+
+      if line.is_empty() {
+        trailing_headers = false;
+      } else if let Some((key, value)) = parse_header(line) {
+        match levels.last_mut() {
+          Some(level) if trailing_headers => apply_header(&mut level.metadata, index + 1, &key, value)?,
+          _ => apply_header(&mut pending, index + 1, &key, value)?,
+        }
+      }
+      // Anything else, like `;` comments or level numbers, is ignored
+    }
+
+    if !map_lines.is_empty() {
+      push_level(&mut levels, &mut map_lines, &mut pending)?;
+    }
+
+    if levels.is_empty() {
+      return Err(LevelError::Empty);
+    }
+    Ok(LevelPack { levels })
+  }
+}
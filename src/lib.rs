@@ -1,12 +1,22 @@
-use ggez::{event::EventHandler, input::keyboard::KeyInput, Context, GameError, GameResult};
-use specs::{System, World, WorldExt};
-
-use components::register_components;
-use resources::{register_resources, InputQueue, Time};
-use maps::initialize_level;
-use systems::event_system::EventSystem;
-use systems::game_play_state_system::GameplayStateSystem;
-use systems::input_system::InputSystem;
+use std::time::Duration;
+
+use ggez::{event::{Axis, Button, EventHandler, MouseButton}, input::{gamepad::GamepadId, keyboard::KeyInput}, Context, GameError, GameResult};
+use specs::System;
+
+use audio::{initialize_sounds, AudioStore};
+use textures::{initialize_textures, TextureStore};
+use camera::Camera;
+pub use maps::{Level, LevelError, LevelMetadata};
+pub use level_pack::LevelPack;
+pub use bindings::{Action, BindingsError, KeyBindings};
+pub use gamepad::{GamepadInput, GamepadSettings, StickChange};
+pub use components::{BoxColor, Direction};
+pub use replay::{parse_lurd, LurdError};
+pub use resources::{AnimationInput, AnimationSettings, Command, GameplayState, Hint, PushRule, RepeatSettings, RuleSet};
+pub use sokoban_core::SokobanCore;
+pub use board::Mechanic;
+pub use solver::{solve, Solution, SolverError, SolverLimits, SolverMode};
+use events::{BoxPlacedOnSpot, Event, ReplayFinished};
 use systems::render_system::RenderSystem;
 
 mod components;
@@ -14,79 +24,328 @@ mod resources;
 mod systems;
 mod entities;
 mod maps;
+mod level_pack;
+mod replay;
 mod constants;
-mod events;
+mod sokoban_core;
+mod board;
+mod solver;
+mod deadlock;
+mod bindings;
+mod gamepad;
+mod textures;
+mod camera;
+pub mod events;
 pub mod audio;
 
+impl From<LevelError> for GameError {
+  fn from(error: LevelError) -> Self {
+    GameError::ResourceLoadError(error.to_string())
+  }
+}
+
+/*
+ggez 前端：把键盘输入转换为指令，把游戏事件转换为声音和画面
+*/
 pub struct Game {
-  world: World,
+  core: SokobanCore,
+  audio_store: AudioStore,
+  texture_store: TextureStore,
+  key_bindings: KeyBindings,
+  gamepad: GamepadInput,
+  camera: Camera,
+  // The level the camera was last fitted to
+  camera_level: Option<usize>,
+  // Where the player was last seen, so the camera only follows it when it
+  // moves and can otherwise be scrolled freely
+  camera_player: Option<(i32, i32)>,
+  // Whether the pull key is held down
+  pulling: bool,
 }
 
+// Read from the user's config directory, e.g. ~/.config/rust_sokoban on Linux
+const KEY_BINDINGS_FILE: &str = "bindings.toml";
+
+// How much a key press or a notch of the mouse wheel zooms in or out
+const ZOOM_STEP: f32 = 1.25;
+
 impl Game {
-  pub fn new(world: World) -> Self {
-    Game { world }
+  pub fn new(context: &mut Context, core: SokobanCore) -> Self {
+    let mut audio_store = AudioStore::default();
+    initialize_sounds(&mut audio_store, context);
+
+    let mut texture_store = TextureStore::default();
+    initialize_textures(&mut texture_store, context);
+
+    let key_bindings_path = context.fs.user_config_dir().join(KEY_BINDINGS_FILE);
+    let key_bindings = KeyBindings::load(&key_bindings_path).unwrap_or_else(|error| {
+      println!("{}: {}, using the default keys", key_bindings_path.display(), error);
+      KeyBindings::default()
+    });
+
+    let mut core = core;
+    core.set_repeat_settings(key_bindings.repeat);
+
+    let (width, height) = context.gfx.drawable_size();
+
+    Game {
+      core,
+      audio_store,
+      texture_store,
+      key_bindings,
+      gamepad: GamepadInput::default(),
+      camera: Camera::new(width, height),
+      camera_level: None,
+      camera_player: None,
+      pulling: false,
+    }
+  }
+
+  pub fn core(&self) -> &SokobanCore {
+    &self.core
+  }
+
+  pub fn core_mut(&mut self) -> &mut SokobanCore {
+    &mut self.core
+  }
+
+  pub fn restart_level(&mut self) {
+    self.core.restart_level();
+  }
+
+  // Asks the solver for the next step. It runs in the background, giving
+  // up after a while on big levels, and `update` shows what it finds.
+  fn request_hint(&mut self) {
+    let limits = SolverLimits {
+      max_time: Duration::from_secs(2),
+      max_states: 500_000,
+    };
+    self.core.request_hint(limits);
+  }
+
+  fn show_hint(&mut self, context: &mut Context, result: Result<Option<Hint>, SolverError>) {
+    match result {
+      Ok(Some(hint)) => println!("Hint: {:?}", hint),
+      Ok(None) => (),
+      Err(error) => {
+        println!("No hint: {}", error);
+        self.audio_store.play_sound(context, "incorrect");
+      }
+    }
+  }
+
+  // Carries out an action from the keyboard or a gamepad. Actions that are
+  // game commands are held, repeating until released.
+  fn perform(&mut self, action: Action) {
+    let command = match action.command() {
+      Some(command) if self.pulling => Some(command.pulling()),
+      command => command,
+    };
+
+    match (action, command) {
+      (_, Some(command)) => self.core.hold(command),
+      (Action::Pull, None) => self.pulling = true,
+      (Action::Restart, None) => self.restart_level(),
+      (Action::Hint, None) => self.request_hint(),
+      (Action::ZoomIn, None) => self.camera.zoom_by(ZOOM_STEP),
+      (Action::ZoomOut, None) => self.camera.zoom_by(1.0 / ZOOM_STEP),
+      (Action::ResetZoom, None) => self.camera.reset_zoom(),
+      _ => (),
+    }
+  }
+
+  fn release(&mut self, action: Action) {
+    if action == Action::Pull {
+      self.pulling = false;
+    }
+    // The action may have been started before or after the pull
+    if let Some(command) = action.command() {
+      self.core.release(command);
+      self.core.release(command.pulling());
+    }
+  }
+
+  // Fits each new level to the window, then keeps the player on screen
+  fn follow_player(&mut self) {
+    let level_index = self.core.level_index();
+    if self.camera_level != Some(level_index) {
+      let (width, height) = self.core.level_size();
+      self.camera.fit_level(width, height);
+      self.camera_level = Some(level_index);
+      self.camera_player = None;
+    }
+
+    let player = self.core.player_position();
+    if player != self.camera_player {
+      if let Some((x, y)) = player {
+        self.camera.keep_in_view(x, y);
+      }
+      self.camera_player = player;
+    }
+  }
+
+  fn handle_event(&mut self, context: &mut Context, event: Event) {
+    println!("New event: {:?}", event);
+
+    match event {
+      Event::PlayerHitObstacle => {
+        self.audio_store.play_sound(context, "wall");
+      }
+      Event::BoxPlacedOnSpot(BoxPlacedOnSpot { is_correct_spot }) => {
+        if is_correct_spot {
+          self.audio_store.play_sound(context, "correct");
+        } else {
+          self.audio_store.play_sound(context, "incorrect");
+        }
+      }
+      Event::ReplayFinished(ReplayFinished { solved }) => {
+        let outcome = if solved { "solved the level" } else { "finished without solving the level" };
+        println!(
+          "Replay {} after {} moves and {} pushes",
+          outcome, self.core.moves_count(), self.core.pushes_count()
+        );
+      }
+      Event::Deadlock(_) => {
+        self.audio_store.play_sound(context, "deadlock");
+      }
+      Event::Teleported(_) => {
+        self.audio_store.play_sound(context, "teleport");
+      }
+      Event::KeyPickedUp(_) => {
+        self.audio_store.play_sound(context, "key");
+      }
+      Event::DoorUnlocked(_) => {
+        self.audio_store.play_sound(context, "door");
+      }
+      Event::GateChanged(_) => {
+        self.audio_store.play_sound(context, "gate");
+      }
+      Event::EntityMoved(_) | Event::PlateChanged(_) | Event::GameOver => (),
+    }
   }
 }
 
 impl EventHandler<GameError> for Game {
-  fn update(&mut self, context: &mut Context) -> GameResult {    
-    {      
-      let mut input_system = InputSystem {};
-      input_system.run(self.world.system_data());
+  fn update(&mut self, context: &mut Context) -> GameResult {
+    self.core.advance_time(context.time.delta());
+
+    for event in self.core.update() {
+      self.handle_event(context, event);
+    }
+    if let Some(result) = self.core.poll_hint() {
+      self.show_hint(context, result);
     }
 
-    {
-      let mut gss = GameplayStateSystem {};
-      gss.run(self.world.system_data());
+    // Moves on to the next level of the pack, or quits once the last one is won
+    if self.core.is_level_completed() {
+      println!("You win! {} moves, {} pushes", self.core.moves_count(), self.core.pushes_count());
+      println!("Solution: {}", self.core.lurd());
+
+      if !self.core.next_level() {
+        println!("All levels completed!");
+        context.request_quit();
+      }
     }
 
+    self.follow_player();
+
+    Ok(())
+  }
+
+  fn draw(&mut self, context: &mut Context) -> GameResult {
     {
-      let mut time = self.world.write_resource::<Time>();
-      time.delta += context.time.delta();
+      let mut rs = RenderSystem::new(context, &mut self.texture_store, &self.camera);
+      rs.run(self.core.world().system_data());
     }
+    Ok(())
+  }
 
-    {
-      let mut event_system = EventSystem { context };
-      event_system.run(self.world.system_data());
+  // The game repeats held keys itself, at the same pace on every system, so
+  // the repeats sent by the OS are ignored
+  fn key_down_event(&mut self, _context: &mut Context, input: KeyInput, repeat: bool) -> GameResult {
+    if repeat {
+      return Ok(());
+    }
+
+    if let Some(action) = input.keycode.and_then(|keycode| self.key_bindings.action(keycode)) {
+      self.perform(action);
     }
 
     Ok(())
   }
 
-  fn draw(&mut self, context: &mut Context) -> GameResult {
-    {
-      let mut rs = RenderSystem::new(context);
-      rs.run(self.world.system_data());
+  fn key_up_event(&mut self, _context: &mut Context, input: KeyInput) -> GameResult {
+    if let Some(action) = input.keycode.and_then(|keycode| self.key_bindings.action(keycode)) {
+      self.release(action);
     }
+
     Ok(())
   }
 
-  fn key_down_event(&mut self, _context: &mut Context, input: KeyInput, _repeat: bool) -> GameResult {
-    let keycode = input.keycode;
-    let mut input_queue = self.world.write_resource::<InputQueue>();
-    input_queue.keys_pressed.push(keycode.unwrap());       
+  fn gamepad_button_down_event(&mut self, _context: &mut Context, button: Button, _id: GamepadId) -> GameResult {
+    if let Some(action) = self.gamepad.button_down(button) {
+      self.perform(action);
+    }
 
     Ok(())
   }
-}
 
-/*
-创建游戏实体
-*/
-pub fn create_game(world: &mut World) {
-  register_components(world);
-  register_resources(world);
-  
-  const MAP: &str = "
-    N N W W W W W W
-    W W W . . . . W
-    W . . . BB . . W
-    W . . . . . . W 
-    W . P . . RB . W
-    W . . . . . . W
-    W . . BS . . . W
-    W . . . . RS . W
-    W W W W W W W W
-    ";  
-  initialize_level(world, MAP);
+  fn gamepad_button_up_event(&mut self, _context: &mut Context, button: Button, _id: GamepadId) -> GameResult {
+    if let Some(action) = self.gamepad.button_up(button) {
+      self.release(action);
+    }
+
+    Ok(())
+  }
+
+  fn gamepad_axis_event(&mut self, _context: &mut Context, axis: Axis, value: f32, _id: GamepadId) -> GameResult {
+    let change = self.gamepad.axis(axis, value);
+    if let Some(action) = change.released {
+      self.release(action);
+    }
+    if let Some(action) = change.pressed {
+      self.perform(action);
+    }
+
+    Ok(())
+  }
+
+  // Scrolling the wheel zooms in and out
+  fn mouse_wheel_event(&mut self, _context: &mut Context, _x: f32, y: f32) -> GameResult {
+    self.camera.zoom_by(ZOOM_STEP.powf(y));
+    Ok(())
+  }
+
+  // Dragging with the right button scrolls levels too big for the window
+  fn mouse_motion_event(&mut self, context: &mut Context, _x: f32, _y: f32, dx: f32, dy: f32) -> GameResult {
+    if context.mouse.button_pressed(MouseButton::Right) {
+      self.camera.scroll(dx, dy);
+    }
+    Ok(())
+  }
+
+  fn resize_event(&mut self, _context: &mut Context, width: f32, height: f32) -> GameResult {
+    self.camera.resize(width, height);
+    Ok(())
+  }
+
+  // Clicking a floor tile walks the player there. Clicking a box selects it,
+  // and the next click pushes it to that tile.
+  fn mouse_button_down_event(&mut self, context: &mut Context, button: MouseButton, x: f32, y: f32) -> GameResult {
+    if button != MouseButton::Left {
+      return Ok(());
+    }
+
+    let (tile_x, tile_y) = self.camera.tile_at(x, y);
+    if self.core.selected_box().is_some() {
+      if !self.core.push_box_to(tile_x, tile_y) {
+        println!("The box can't be pushed to {}, {}", tile_x, tile_y);
+        self.audio_store.play_sound(context, "incorrect");
+      }
+    } else if !self.core.select_box_at(tile_x, tile_y) && !self.core.walk_to(tile_x, tile_y) {
+      println!("Can't walk to {}, {}", tile_x, tile_y);
+    }
+
+    Ok(())
+  }
 }
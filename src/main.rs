@@ -1,23 +1,166 @@
-use std::path;
-use ggez::{conf, event, GameResult};
-use rust_sokoban::{create_game, Game, audio::initialize_sounds};
-use specs::{World, WorldExt};
+use std::{env, fs, path, time::Duration};
+use ggez::{conf, event, GameError, GameResult};
+use rust_sokoban::{parse_lurd, solve, AnimationInput, AnimationSettings, Game, LevelPack, SokobanCore, SolverLimits, SolverMode};
+
+const USAGE: &str = "usage: rust_sokoban [LEVEL_FILE] [--level N] [--replay SOLUTION] [--replay-interval MS] [--animation MS] [--fast-forward]
+       rust_sokoban solve [LEVEL_FILE] [--level N] [--moves] [--timeout SECS] [--max-states N]";
+
+const DEFAULT_LEVEL_PATH: &str = "./resources/levels/default.xsb";
+
+struct Options {
+  level_path: String,
+  // 1-based level number to start from
+  level: Option<usize>,
+  // A LURD string, or the path of a file holding one
+  replay: Option<String>,
+  replay_interval: Duration,
+  animation: AnimationSettings,
+}
+
+fn parse_options() -> Result<Options, String> {
+  let mut options = Options {
+    level_path: DEFAULT_LEVEL_PATH.to_string(),
+    level: None,
+    replay: None,
+    replay_interval: Duration::from_millis(150),
+    animation: AnimationSettings {
+      duration: Duration::from_millis(120),
+      input: AnimationInput::Buffer,
+    },
+  };
+
+  let mut args = env::args().skip(1);
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--level" => {
+        let level = args.next().and_then(|n| n.parse::<usize>().ok()).filter(|n| *n > 0);
+        options.level = Some(level.ok_or("--level expects a level number")?);
+      }
+      "--replay" => options.replay = Some(args.next().ok_or("--replay expects a solution")?),
+      "--replay-interval" => {
+        let millis = args.next().and_then(|ms| ms.parse::<u64>().ok());
+        options.replay_interval = Duration::from_millis(millis.ok_or("--replay-interval expects milliseconds")?);
+      }
+      "--animation" => {
+        let millis = args.next().and_then(|ms| ms.parse::<u64>().ok());
+        options.animation.duration = Duration::from_millis(millis.ok_or("--animation expects milliseconds")?);
+      }
+      "--fast-forward" => options.animation.input = AnimationInput::FastForward,
+      _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+      _ => options.level_path = arg,
+    }
+  }
+
+  Ok(options)
+}
+
+struct SolveOptions {
+  level_path: String,
+  // 1-based level number to solve, every level of the pack otherwise
+  level: Option<usize>,
+  mode: SolverMode,
+  limits: SolverLimits,
+}
+
+fn parse_solve_options() -> Result<SolveOptions, String> {
+  let mut options = SolveOptions {
+    level_path: DEFAULT_LEVEL_PATH.to_string(),
+    level: None,
+    mode: SolverMode::Pushes,
+    limits: SolverLimits::default(),
+  };
+
+  let mut args = env::args().skip(2);
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--level" => {
+        let level = args.next().and_then(|n| n.parse::<usize>().ok()).filter(|n| *n > 0);
+        options.level = Some(level.ok_or("--level expects a level number")?);
+      }
+      "--moves" => options.mode = SolverMode::Moves,
+      "--timeout" => {
+        let seconds = args.next().and_then(|s| s.parse::<u64>().ok());
+        options.limits.max_time = Duration::from_secs(seconds.ok_or("--timeout expects seconds")?);
+      }
+      "--max-states" => {
+        let states = args.next().and_then(|n| n.parse::<usize>().ok());
+        options.limits.max_states = states.ok_or("--max-states expects a number")?;
+      }
+      _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+      _ => options.level_path = arg,
+    }
+  }
+
+  Ok(options)
+}
+
+// Solves levels from the command line without opening a window
+fn run_solver() -> GameResult {
+  let options = parse_solve_options().map_err(|error| GameError::CustomError(format!("{}\n{}", error, USAGE)))?;
+  let level_pack = LevelPack::from_file(&options.level_path)?;
+
+  let numbers = match options.level {
+    Some(level) if level > level_pack.len() => {
+      return Err(GameError::CustomError(format!("{} only has {} levels", options.level_path, level_pack.len())));
+    }
+    Some(level) => level..=level,
+    None => 1..=level_pack.len(),
+  };
+
+  for number in numbers {
+    let level = &level_pack.levels()[number - 1];
+    let title = level.metadata.title.as_deref().unwrap_or("");
+    match solve(level, options.mode, options.limits) {
+      Ok(solution) => println!(
+        "Level {} {}: {} moves, {} pushes\n{}",
+        number, title, solution.moves, solution.pushes, solution.lurd
+      ),
+      Err(error) => println!("Level {} {}: {}", number, title, error),
+    }
+  }
+
+  Ok(())
+}
+
 fn main() -> GameResult {
-  
-  let mut world = World::new();
+  if env::args().nth(1).as_deref() == Some("solve") {
+    return run_solver();
+  }
+
+  let options = parse_options().map_err(|error| GameError::CustomError(format!("{}\n{}", error, USAGE)))?;
+
+  let mut core = SokobanCore::from_file(&options.level_path)?;
+
+  if let Some(level) = options.level {
+    if level > core.level_count() {
+      return Err(GameError::CustomError(format!("{} only has {} levels", options.level_path, core.level_count())));
+    }
+    core.load_level(level - 1);
+  }
+
+  core.set_animation_settings(options.animation);
+
+  if let Some(replay) = options.replay {
+    let solution = if path::Path::new(&replay).is_file() {
+      fs::read_to_string(&replay)?
+    } else {
+      replay
+    };
+    let steps = parse_lurd(&solution).map_err(|error| GameError::CustomError(error.to_string()))?;
+    core.replay(steps, options.replay_interval);
+  }
+
     // Create a game context and event loop
   let context_builder = ggez::ContextBuilder::new("rust_sokoban", "sokoban")
       .window_setup(conf::WindowSetup::default().title("Rust Sokoban!"))
-      .window_mode(conf::WindowMode::default().dimensions(800.0, 600.0))
+      .window_mode(conf::WindowMode::default().dimensions(800.0, 600.0).resizable(true))
       .add_resource_path(path::PathBuf::from("./resources"));
 
   let (mut context, event_loop) = context_builder.build()?;
 
-  create_game(&mut world);
-  initialize_sounds(&mut world, &mut context);
-
   // Create the game state
-  let game = Game::new(world);
+  let game = Game::new(&mut context, core);
+
   // Run the main event loop
   event::run(context, event_loop, game)
 }
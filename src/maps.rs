@@ -1,47 +1,372 @@
-use specs::World;
+use std::{fmt::{self, Display}, fs, io, path::Path, str::FromStr};
 
-use crate::{components::{BoxColor, Position}, entities::{create_box, create_box_spot, create_floor, create_player, create_wall}};
+use specs::{World, WorldExt};
 
-pub fn initialize_level(world: &mut World, map_string: &str) {
-  load_map(world, map_string.to_string());
+use crate::{components::{BoxColor, Position}, entities::{create_box, create_box_spot, create_floor, create_door, create_gate, create_ice, create_key, create_plate, create_teleporter, create_player, create_wall}, level_pack::LevelPack, resources::{BoxSelection, Keyring, RuleSet, CurrentHint, CurrentLevel, EventQueue, Gameplay, InputQueue, MapBounds, MoveHistory, Replay}};
+
+// XSB has no notion of box colors, so every box and spot read from a
+// level file gets the same one, and red ones are never created.
+const XSB_BOX_COLOR: BoxColor = BoxColor::Blue;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terrain {
+  // Outside of the level, nothing gets created here
+  Void,
+  Floor,
+  // Floor that whatever moves onto slides over
+  Ice,
+  Wall,
 }
 
-fn load_map(world: &mut World, map: String) {
-  let lines: Vec<&str> = map.trim().split('\n').map(|line| line.trim()).collect();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Occupant {
+  Player,
+  Box(BoxColor),
+}
 
-  for (y, line) in lines.iter().enumerate() {
-    let columns: Vec<&str> = line.split(' ').collect();
-    for (x, c) in columns.iter().enumerate() {
-      let position = Position { x: x as u8, y: y as u8, z: 0 };
-      match *c {
-        "." => create_floor(world, position),
-        "W" => {
-          create_floor(world, position);
-          create_wall(world, position);
-        } 
-        "P" => {
-          create_floor(world, position);
-          create_player(world, position);
+// Things on the floor that take part in puzzles besides boxes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fixture {
+  Key(BoxColor),
+  Door(BoxColor),
+  Plate,
+  Gate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+  pub terrain: Terrain,
+  pub spot: Option<BoxColor>,
+  pub occupant: Option<Occupant>,
+  // The channel of the teleporter on the cell, linking it to the other one
+  pub teleporter: Option<u8>,
+  pub fixture: Option<Fixture>,
+}
+
+impl Cell {
+  const VOID: Cell = Cell { terrain: Terrain::Void, spot: None, occupant: None, teleporter: None, fixture: None };
+}
+
+/*
+关卡信息
+*/
+#[derive(Debug, Clone, Default)]
+pub struct LevelMetadata {
+  pub title: Option<String>,
+  pub author: Option<String>,
+  pub comment: Option<String>,
+  // Levels without `Rules:` or `Pull:` headers play by the classic rules
+  pub rules: RuleSet,
+}
+
+/*
+解析后的关卡
+*/
+#[derive(Debug, Clone)]
+pub struct Level {
+  pub metadata: LevelMetadata,
+  width: usize,
+  height: usize,
+  cells: Vec<Cell>,
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+  Io(io::Error),
+  Empty,
+  UnrecognizedItem { line: usize, column: usize, item: char },
+  MissingPlayer,
+  MultiplePlayers,
+  BoxSpotMismatch { boxes: usize, spots: usize },
+  UnknownRule { line: usize, rule: String },
+  UnpairedTeleporter { channel: u8, count: usize },
+  GatesWithoutPlates,
+  InLevel { level: usize, error: Box<LevelError> },
+}
+
+impl Display for LevelError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      LevelError::Io(error) => write!(f, "could not read level: {}", error),
+      LevelError::Empty => write!(f, "level is empty"),
+      LevelError::UnrecognizedItem { line, column, item } => {
+        write!(f, "unrecognized map item {:?} at line {}, column {}", item, line, column)
+      }
+      LevelError::MissingPlayer => write!(f, "level has no player"),
+      LevelError::MultiplePlayers => write!(f, "level has more than one player"),
+      LevelError::BoxSpotMismatch { boxes, spots } => {
+        write!(f, "level has {} boxes but {} box spots", boxes, spots)
+      }
+      LevelError::UnknownRule { line, rule } => write!(f, "unknown rule {:?} at line {}", rule, line),
+      LevelError::UnpairedTeleporter { channel, count } => {
+        write!(f, "level has {} teleporters on channel {}, expected 2", count, channel)
+      }
+      LevelError::GatesWithoutPlates => write!(f, "level has gates but no pressure plates"),
+      LevelError::InLevel { level, error } => write!(f, "level {}: {}", level, error),
+    }
+  }
+}
+
+impl std::error::Error for LevelError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      LevelError::Io(error) => Some(error),
+      LevelError::InLevel { error, .. } => Some(error.as_ref()),
+      _ => None,
+    }
+  }
+}
+
+impl From<io::Error> for LevelError {
+  fn from(error: io::Error) -> Self {
+    LevelError::Io(error)
+  }
+}
+
+impl Level {
+  pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, LevelError> {
+    fs::read_to_string(path)?.parse()
+  }
+
+  pub fn width(&self) -> usize {
+    self.width
+  }
+
+  pub fn height(&self) -> usize {
+    self.height
+  }
+
+  pub fn cell(&self, x: usize, y: usize) -> Cell {
+    if x < self.width && y < self.height {
+      self.cells[y * self.width + x]
+    } else {
+      Cell::VOID
+    }
+  }
+
+  // A map line only holds map items and has at least one wall on it, which
+  // tells it apart from blank lines and free text in level pack files.
+  pub(crate) fn is_map_line(line: &str) -> bool {
+    line.contains('#') && line.chars().all(|item| Level::parse_item(item).is_some())
+  }
+
+  // Parses the map rows of a single level, each paired with its line number
+  // in the source so errors can point at the right place.
+  pub(crate) fn parse_lines(lines: &[(usize, &str)]) -> Result<Self, LevelError> {
+    let width = lines.iter().map(|(_, line)| line.chars().count()).max().unwrap_or(0);
+    let height = lines.len();
+    if width == 0 {
+      return Err(LevelError::Empty);
+    }
+
+    let mut cells = vec![Cell::VOID; width * height];
+    let mut player = None;
+    let (mut boxes, mut spots) = (0, 0);
+    let mut teleporters = [0; 10];
+    let (mut gates, mut plates) = (0, 0);
+
+    for (y, (line_number, line)) in lines.iter().enumerate() {
+      for (x, item) in line.chars().enumerate() {
+        let cell = Level::parse_item(item).ok_or(LevelError::UnrecognizedItem {
+          line: *line_number,
+          column: x + 1,
+          item,
+        })?;
+
+        match cell.occupant {
+          Some(Occupant::Player) if player.is_some() => return Err(LevelError::MultiplePlayers),
+          Some(Occupant::Player) => player = Some((x, y)),
+          Some(Occupant::Box(_)) => boxes += 1,
+          None => (),
         }
-        "BB" =>{
-          create_floor(world, position);
-          create_box(world, position, BoxColor::Blue);
+        if cell.spot.is_some() {
+          spots += 1;
         }
-        "RB" =>{
-          create_floor(world, position);
-          create_box(world, position, BoxColor::Red);
+        if let Some(channel) = cell.teleporter {
+          teleporters[channel as usize] += 1;
         }
-        "BS" => {
-          create_floor(world, position);
-          create_box_spot(world, position, BoxColor::Blue);
+        match cell.fixture {
+          Some(Fixture::Gate) => gates += 1,
+          Some(Fixture::Plate) => plates += 1,
+          _ => (),
         }
-        "RS" => {
+
+        cells[y * width + x] = cell;
+      }
+    }
+
+    let player = player.ok_or(LevelError::MissingPlayer)?;
+    if boxes != spots {
+      return Err(LevelError::BoxSpotMismatch { boxes, spots });
+    }
+    // Teleporters come in pairs, so there is always exactly one way out
+    if let Some((channel, count)) = teleporters.iter().enumerate().find(|(_, count)| **count != 0 && **count != 2) {
+      return Err(LevelError::UnpairedTeleporter { channel: channel as u8, count: *count });
+    }
+    // Gates only open while the plates are pressed, so they'd be walls
+    if gates > 0 && plates == 0 {
+      return Err(LevelError::GatesWithoutPlates);
+    }
+
+    let mut level = Level { metadata: LevelMetadata::default(), width, height, cells };
+    level.fill_floor(player);
+    Ok(level)
+  }
+
+  fn parse_item(item: char) -> Option<Cell> {
+    let floor = Cell { terrain: Terrain::Floor, ..Cell::VOID };
+    let cell = match item {
+      ' ' | '-' | '_' => Cell::VOID,
+      '#' => Cell { terrain: Terrain::Wall, ..Cell::VOID },
+      '@' => Cell { occupant: Some(Occupant::Player), ..floor },
+      '+' => Cell { occupant: Some(Occupant::Player), spot: Some(XSB_BOX_COLOR), ..floor },
+      '$' => Cell { occupant: Some(Occupant::Box(XSB_BOX_COLOR)), ..floor },
+      '*' => Cell { occupant: Some(Occupant::Box(XSB_BOX_COLOR)), spot: Some(XSB_BOX_COLOR), ..floor },
+      '.' => Cell { spot: Some(XSB_BOX_COLOR), ..floor },
+      '~' => Cell { terrain: Terrain::Ice, ..Cell::VOID },
+      '1'..='9' => Cell { teleporter: item.to_digit(10).map(|channel| channel as u8), ..floor },
+      // Some XSB variants use b/B for boxes, so blue keys and doors are u/U
+      'u' => Cell { fixture: Some(Fixture::Key(BoxColor::Blue)), ..floor },
+      'r' => Cell { fixture: Some(Fixture::Key(BoxColor::Red)), ..floor },
+      'U' => Cell { fixture: Some(Fixture::Door(BoxColor::Blue)), ..floor },
+      'R' => Cell { fixture: Some(Fixture::Door(BoxColor::Red)), ..floor },
+      // Plates aren't tied to single gates: every gate of the level opens
+      // while all of its plates are pressed
+      '^' => Cell { fixture: Some(Fixture::Plate), ..floor },
+      '=' => Cell { fixture: Some(Fixture::Gate), ..floor },
+      _ => return None,
+    };
+    Some(cell)
+  }
+
+  // Blank cells in XSB are used both for the floor inside the walls and for
+  // the padding outside of them. Everything the player can walk to is floor.
+  fn fill_floor(&mut self, player: (usize, usize)) {
+    let mut visited = vec![false; self.cells.len()];
+    let mut stack = vec![player];
+    // Both pads of every channel, so the fill goes on from wherever a pad leads
+    let mut pads: [Vec<(usize, usize)>; 10] = Default::default();
+    for (index, cell) in self.cells.iter().enumerate() {
+      if let Some(channel) = cell.teleporter {
+        pads[channel as usize].push((index % self.width, index / self.width));
+      }
+    }
+
+    while let Some((x, y)) = stack.pop() {
+      let index = y * self.width + x;
+      if visited[index] || self.cells[index].terrain == Terrain::Wall {
+        continue;
+      }
+      visited[index] = true;
+      if self.cells[index].terrain == Terrain::Void {
+        self.cells[index].terrain = Terrain::Floor;
+      }
+      if let Some(channel) = self.cells[index].teleporter {
+        stack.extend(pads[channel as usize].iter().copied());
+      }
+
+      if x > 0 {
+        stack.push((x - 1, y));
+      }
+      if x + 1 < self.width {
+        stack.push((x + 1, y));
+      }
+      if y > 0 {
+        stack.push((x, y - 1));
+      }
+      if y + 1 < self.height {
+        stack.push((x, y + 1));
+      }
+    }
+  }
+}
+
+impl FromStr for Level {
+  type Err = LevelError;
+
+  fn from_str(map: &str) -> Result<Self, Self::Err> {
+    // Leading spaces are significant in XSB, so only the line endings are trimmed
+    let lines: Vec<(usize, &str)> = map
+      .lines()
+      .enumerate()
+      .map(|(index, line)| (index + 1, line.trim_end()))
+      .skip_while(|(_, line)| line.is_empty())
+      .collect();
+    match lines.iter().rposition(|(_, line)| !line.is_empty()) {
+      Some(last) => Level::parse_lines(&lines[..=last]),
+      None => Err(LevelError::Empty),
+    }
+  }
+}
+
+/*
+加载当前关卡，清除上一关卡的所有实体
+*/
+pub fn initialize_level(world: &mut World) {
+  let level = {
+    let level_pack = world.read_resource::<LevelPack>();
+    let current_level = world.read_resource::<CurrentLevel>();
+    level_pack.level(current_level.index).cloned()
+  };
+
+  world.delete_all();
+  *world.write_resource::<Gameplay>() = Gameplay::default();
+  world.write_resource::<InputQueue>().commands.clear();
+  *world.write_resource::<EventQueue>() = EventQueue::default();
+  *world.write_resource::<MoveHistory>() = MoveHistory::default();
+  *world.write_resource::<Replay>() = Replay::default();
+  *world.write_resource::<CurrentHint>() = CurrentHint::default();
+  *world.write_resource::<BoxSelection>() = BoxSelection::default();
+  *world.write_resource::<Keyring>() = Keyring::default();
+  world.write_resource::<CurrentLevel>().completed = false;
+  *world.write_resource::<RuleSet>() = level.as_ref().map(|level| level.metadata.rules).unwrap_or_default();
+
+  if let Some(level) = level {
+    *world.write_resource::<MapBounds>() = MapBounds {
+      width: level.width() as i32,
+      height: level.height() as i32,
+    };
+    load_map(world, &level);
+  }
+}
+
+fn load_map(world: &mut World, level: &Level) {
+  for y in 0..level.height() {
+    for x in 0..level.width() {
+      let cell = level.cell(x, y);
+      let position = Position { x: x as i32, y: y as i32, z: 0 };
+
+      match cell.terrain {
+        Terrain::Void => continue,
+        Terrain::Floor => create_floor(world, position),
+        Terrain::Ice => create_ice(world, position),
+        Terrain::Wall => {
           create_floor(world, position);
-          create_box_spot(world, position, BoxColor::Red);
+          create_wall(world, position);
         }
-        "N" => (),
-        _ => panic!("unrecognized map item {}", c),
+      }
+
+      if let Some(color) = cell.spot {
+        create_box_spot(world, position, color);
+      }
+
+      if let Some(channel) = cell.teleporter {
+        create_teleporter(world, position, channel);
+      }
+
+      match cell.fixture {
+        Some(Fixture::Key(color)) => create_key(world, position, color),
+        Some(Fixture::Door(color)) => create_door(world, position, color),
+        Some(Fixture::Plate) => create_plate(world, position),
+        Some(Fixture::Gate) => create_gate(world, position),
+        None => (),
+      }
+
+      match cell.occupant {
+        Some(Occupant::Player) => create_player(world, position),
+        Some(Occupant::Box(color)) => create_box(world, position, color),
+        None => (),
       }
     }
-  } 
-}
\ No newline at end of file
+  }
+}
@@ -0,0 +1,43 @@
+use std::fmt::{self, Display};
+
+use crate::{components::Direction, resources::{Command, PULL_MARKER}};
+
+#[derive(Debug)]
+pub struct LurdError {
+  pub position: usize,
+  pub step: char,
+}
+
+impl Display for LurdError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "invalid step {:?} at position {} of the solution", self.step, self.position + 1)
+  }
+}
+
+impl std::error::Error for LurdError {}
+
+/*
+解析 LURD 格式的解法，`^` 开头的一步是拉箱子
+*/
+pub fn parse_lurd(solution: &str) -> Result<Vec<Command>, LurdError> {
+  let mut steps = solution.chars().enumerate().filter(|(_, step)| !step.is_whitespace());
+  let mut commands = Vec::new();
+
+  while let Some((position, step)) = steps.next() {
+    let command = if step == PULL_MARKER {
+      // Only a box can be pulled, so the marked step has to be uppercase
+      match steps.next() {
+        Some((position, step)) => match Direction::from_lurd(step) {
+          Some(direction) if step.is_ascii_uppercase() => Command::Pull(direction),
+          _ => return Err(LurdError { position, step }),
+        },
+        None => return Err(LurdError { position, step }),
+      }
+    } else {
+      Command::Move(Direction::from_lurd(step).ok_or(LurdError { position, step })?)
+    };
+    commands.push(command);
+  }
+
+  Ok(commands)
+}
@@ -1,22 +1,144 @@
-use std::{collections::HashMap, fmt::{self, Display}, time::Duration};
+use std::{collections::VecDeque, fmt::{self, Display}, time::Duration};
 
-use ggez::{audio, input::keyboard::KeyCode};
 use specs::World;
 
-use crate::events::Event;
+use crate::{components::{BoxColor, Direction, Position}, events::{EntityId, Event}, level_pack::LevelPack};
+
+/*
+玩家指令
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+  Move(Direction),
+  // Steps away from the box behind the player, dragging it along, in levels
+  // that allow pulling. Elsewhere it is a normal move.
+  Pull(Direction),
+  Undo,
+  Redo,
+}
+
+impl Command {
+  // The same step, pulling instead of pushing
+  pub fn pulling(self) -> Self {
+    match self {
+      Command::Move(direction) => Command::Pull(direction),
+      command => command,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueuedCommand {
+  pub command: Command,
+  // Key presses are dropped when they wait too long, so holding a key or
+  // mashing it doesn't leave a backlog of steps behind
+  pub expires_at: Option<Duration>,
+}
 
-#[derive(Default)]
 pub struct InputQueue {
-  pub keys_pressed: Vec<KeyCode>,
+  // Handled in order, one per update
+  pub commands: VecDeque<QueuedCommand>,
+  // How long a key press may wait before it is dropped
+  pub stale_after: Duration,
+}
+
+impl Default for InputQueue {
+  fn default() -> Self {
+    InputQueue {
+      commands: VecDeque::new(),
+      stale_after: Duration::from_millis(250),
+    }
+  }
+}
+
+impl InputQueue {
+  // Queues a command that is handled however long it waits
+  pub fn push(&mut self, command: Command) {
+    self.commands.push_back(QueuedCommand { command, expires_at: None });
+  }
+
+  pub fn extend<I: IntoIterator<Item = Command>>(&mut self, commands: I) {
+    for command in commands {
+      self.push(command);
+    }
+  }
+
+  // Queues a key press, which goes stale after `stale_after`
+  pub fn push_expiring(&mut self, command: Command, now: Duration) {
+    let expires_at = Some(now + self.stale_after);
+    self.commands.push_back(QueuedCommand { command, expires_at });
+  }
+
+  // The next command to handle, skipping those that went stale
+  pub fn pop(&mut self, now: Duration) -> Option<Command> {
+    while let Some(queued) = self.commands.pop_front() {
+      if queued.expires_at.is_none_or(|expires_at| now <= expires_at) {
+        return Some(queued.command);
+      }
+    }
+    None
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RepeatSettings {
+  // How long a key is held before it starts repeating
+  pub delay: Duration,
+  pub interval: Duration,
+}
+
+impl Default for RepeatSettings {
+  fn default() -> Self {
+    RepeatSettings {
+      delay: Duration::from_millis(250),
+      interval: Duration::from_millis(100),
+    }
+  }
+}
+
+/*
+按住的按键，由游戏按固定节奏重复，而不是依赖系统的按键重复
+*/
+#[derive(Default)]
+pub struct KeyRepeat {
+  pub settings: RepeatSettings,
+  pub held: Option<Command>,
+  pub next_repeat_at: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AnimationInput {
+  // Commands wait for the running animation to end
+  #[default]
+  Buffer,
+  // Commands are handled right away and the running animation jumps to its end
+  FastForward,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AnimationSettings {
+  // How long a step takes on screen, zero to jump straight to the next tile
+  pub duration: Duration,
+  pub input: AnimationInput,
+}
+
+/*
+移动动画的状态
+*/
+#[derive(Default)]
+pub struct Animation {
+  pub settings: AnimationSettings,
+  pub in_progress: bool,
 }
 
 #[derive(Default)]
 pub struct Gameplay {
   pub state: GameplayState,
-  pub moves_count: u32
+  pub moves_count: u32,
+  pub pushes_count: u32,
 }
 
-#[derive(Default, PartialEq)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum GameplayState {
   #[default]
   Playing,
@@ -38,21 +160,226 @@ pub struct Time {
   pub delta: Duration,
 }
 
+// A single entity that was moved by a step, with its position before and after
+#[derive(Debug, Clone, Copy)]
+pub struct EntityMove {
+  pub id: EntityId,
+  pub from: (i32, i32),
+  pub to: (i32, i32),
+  // Whether it went through a teleporter on the way
+  pub teleported: bool,
+}
+
+// Something a step did besides moving entities. Keys and opened doors are
+// taken off the board, and undoing the step puts them back where they were.
+#[derive(Debug, Clone, Copy)]
+pub enum Effect {
+  KeyTaken { id: EntityId, position: Position, color: BoxColor },
+  DoorOpened { id: EntityId, position: Position, color: BoxColor },
+}
+
+// Written before a step in LURD solutions to make it a pull, e.g. `^R`
+pub const PULL_MARKER: char = '^';
+
+#[derive(Debug, Clone)]
+pub struct MoveRecord {
+  pub direction: Direction,
+  // The player comes first, followed by any boxes it pushed or pulled
+  pub moves: Vec<EntityMove>,
+  pub effects: Vec<Effect>,
+  // Whether the box that moved was pulled rather than pushed
+  pub pulled: bool,
+}
+
+impl MoveRecord {
+  // Anything moving besides the player means a box was pushed or pulled
+  pub fn is_push(&self) -> bool {
+    self.moves.len() > 1
+  }
+
+  // The step in LURD notation: lowercase for a walk, uppercase for a push.
+  // LURD has no letters for pulls, so they are pushes marked with `PULL_MARKER`.
+  pub fn lurd(&self) -> String {
+    let step = self.direction.lurd();
+    match (self.is_push(), self.pulled) {
+      (true, true) => format!("{}{}", PULL_MARKER, step.to_ascii_uppercase()),
+      (true, false) => step.to_ascii_uppercase().to_string(),
+      (false, _) => step.to_string(),
+    }
+  }
+}
+
+/*
+移动历史，用于撤销和重做
+*/
+#[derive(Default)]
+pub struct MoveHistory {
+  pub done: Vec<MoveRecord>,
+  pub undone: Vec<MoveRecord>,
+}
+
+impl MoveHistory {
+  // All the steps taken so far, as a LURD string
+  pub fn lurd(&self) -> String {
+    self.done.iter().map(MoveRecord::lurd).collect()
+  }
+}
+
+/*
+回放解法的状态
+*/
+#[derive(Default)]
+pub struct Replay {
+  pub active: bool,
+  pub steps: VecDeque<Command>,
+  // Time to wait between two replayed steps
+  pub interval: Duration,
+  pub next_step_at: Duration,
+}
+
+/*
+提示：建议的下一步，只显示不执行
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hint {
+  pub direction: Direction,
+  // The box the suggested step pushes, if any
+  pub pushed_box: Option<EntityId>,
+}
+
+#[derive(Default)]
+pub struct CurrentHint {
+  pub hint: Option<Hint>,
+}
+
+/*
+用鼠标选中的箱子，下一次点击决定把它推到哪里
+*/
+#[derive(Default)]
+pub struct BoxSelection {
+  pub selected: Option<EntityId>,
+}
+
+/*
+玩家捡到、还没用掉的钥匙
+*/
+#[derive(Debug, Default)]
+pub struct Keyring {
+  pub keys: Vec<BoxColor>,
+}
+
+impl Keyring {
+  // Uses up a key of the given color, if there is one
+  pub fn take(&mut self, color: BoxColor) -> bool {
+    match self.keys.iter().position(|key| *key == color) {
+      Some(index) => {
+        self.keys.remove(index);
+        true
+      }
+      None => false,
+    }
+  }
+}
+
 #[derive(Default)]
 pub struct EventQueue {
   pub events: Vec<Event>,
+  // Events the game rules are done with, waiting to be picked up by whoever
+  // drives the game to play sounds and the like
+  pub handled: Vec<Event>,
+}
+
+/*
+地图边界，由当前关卡的尺寸决定
+*/
+#[derive(Default, Clone, Copy)]
+pub struct MapBounds {
+  pub width: i32,
+  pub height: i32,
+}
+
+impl MapBounds {
+  pub fn contains(&self, x: i32, y: i32) -> bool {
+    x >= 0 && y >= 0 && x < self.width && y < self.height
+  }
+
+  pub fn index(&self, x: i32, y: i32) -> Option<usize> {
+    if self.contains(x, y) {
+      Some((y * self.width + x) as usize)
+    } else {
+      None
+    }
+  }
+
+  pub fn area(&self) -> usize {
+    (self.width * self.height) as usize
+  }
 }
 
 #[derive(Default)]
-pub struct AudioStore {
-  pub sounds: HashMap<String, audio::Source>,
+pub struct CurrentLevel {
+  pub index: usize,
+  // Set once the level is won, the game moves on to the next one
+  pub completed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PushRule {
+  // One box at a time, a box in front of another can't be pushed
+  #[default]
+  Classic,
+  // A row of up to `strength` boxes moves together
+  Chain { strength: usize },
+  // The whole row moves, as long as there is a gap at its end
+  Unlimited,
+}
+
+impl PushRule {
+  // The most boxes a single step can push, if there is a limit
+  pub fn max_boxes(self) -> Option<usize> {
+    match self {
+      PushRule::Classic => Some(1),
+      PushRule::Chain { strength } => Some(strength),
+      PushRule::Unlimited => None,
+    }
+  }
+}
+
+impl Display for PushRule {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      PushRule::Classic => write!(f, "classic"),
+      PushRule::Chain { strength } => write!(f, "chain {}", strength),
+      PushRule::Unlimited => write!(f, "unlimited"),
+    }
+  }
+}
+
+/*
+关卡规则，随关卡一起加载
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RuleSet {
+  pub push: PushRule,
+  // Whether boxes can be pulled as well as pushed
+  pub pull: bool,
 }
 
 pub fn register_resources(world: &mut World) {
   world.insert(InputQueue::default());
+  world.insert(KeyRepeat::default());
+  world.insert(Animation::default());
   world.insert(Gameplay::default());
   world.insert(Time::default());
   world.insert(EventQueue::default());
-  world.insert(AudioStore::default());
+  world.insert(MoveHistory::default());
+  world.insert(Replay::default());
+  world.insert(CurrentHint::default());
+  world.insert(BoxSelection::default());
+  world.insert(Keyring::default());
+  world.insert(MapBounds::default());
+  world.insert(CurrentLevel::default());
+  world.insert(RuleSet::default());
+  world.insert(LevelPack::default());
 }
 
@@ -0,0 +1,405 @@
+use std::{path::Path, sync::mpsc::{self, Receiver, TryRecvError}, thread, time::Duration};
+
+use specs::{Component, Join, System, World, WorldExt};
+
+use crate::{
+  board::{Board, BoardState},
+  components::{register_components, Box, Direction, Player, Position, VisualPosition},
+  events::{EntityId, Event},
+  level_pack::LevelPack,
+  maps::{initialize_level, LevelError},
+  resources::{register_resources, Animation, AnimationSettings, BoxSelection, Command, CurrentHint, CurrentLevel, Hint, EventQueue, Gameplay, GameplayState, InputQueue, KeyRepeat, MapBounds, MoveHistory, QueuedCommand, RepeatSettings, Replay, RuleSet, Time},
+  solver::{occupancy, push_path, solve_board, walk_path, Solution, SolverError, SolverLimits, SolverMode},
+  systems::{animation_system::AnimationSystem, event_system::EventSystem, game_play_state_system::GameplayStateSystem, gate_system::GateSystem, input_system::InputSystem, replay_system::ReplaySystem, repeat_system::RepeatSystem},
+};
+
+/*
+无界面的游戏核心：移动、推箱子、胜利判断和事件，不依赖窗口或声音设备
+*/
+pub struct SokobanCore {
+  world: World,
+  pending_hint: Option<PendingHint>,
+}
+
+// A hint being worked out on another thread, for the boxes where they were
+// when it was asked for
+struct PendingHint {
+  start: BoardState,
+  receiver: Receiver<Result<Solution, SolverError>>,
+}
+
+impl SokobanCore {
+  pub fn new(level_pack: LevelPack) -> Self {
+    let mut world = World::new();
+    register_components(&mut world);
+    register_resources(&mut world);
+
+    world.insert(level_pack);
+    initialize_level(&mut world);
+
+    SokobanCore { world, pending_hint: None }
+  }
+
+  pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, LevelError> {
+    Ok(SokobanCore::new(LevelPack::from_file(path)?))
+  }
+
+  pub(crate) fn world(&self) -> &World {
+    &self.world
+  }
+
+  pub fn level_count(&self) -> usize {
+    self.world.read_resource::<LevelPack>().len()
+  }
+
+  // Width and height of the current level, in tiles
+  pub fn level_size(&self) -> (i32, i32) {
+    let map_bounds = self.world.read_resource::<MapBounds>();
+    (map_bounds.width, map_bounds.height)
+  }
+
+  // The rules the current level is played by
+  pub fn rules(&self) -> RuleSet {
+    *self.world.read_resource::<RuleSet>()
+  }
+
+  pub fn level_index(&self) -> usize {
+    self.world.read_resource::<CurrentLevel>().index
+  }
+
+  // Jumps to the level at the given index of the pack
+  pub fn load_level(&mut self, index: usize) {
+    self.world.write_resource::<CurrentLevel>().index = index;
+    initialize_level(&mut self.world);
+  }
+
+  // Throws away the current state of the level and builds it again from the
+  // level pack, as if it had just been loaded
+  pub fn restart_level(&mut self) {
+    initialize_level(&mut self.world);
+  }
+
+  // Moves on to the next level of the pack. Returns false, leaving the
+  // current level as it is, when it was the last one.
+  pub fn next_level(&mut self) -> bool {
+    let next_index = self.level_index() + 1;
+    if next_index >= self.level_count() {
+      return false;
+    }
+
+    self.load_level(next_index);
+    true
+  }
+
+  // Queues a command to be handled by the next update
+  pub fn push_command(&mut self, command: Command) {
+    self.world.write_resource::<InputQueue>().push(command);
+  }
+
+  // Queues a key press. Unlike `push_command` it is dropped if it waits too
+  // long, e.g. while an earlier walk is still being played out.
+  pub fn press(&mut self, command: Command) {
+    let now = self.time();
+    self.world.write_resource::<InputQueue>().push_expiring(command, now);
+  }
+
+  // Presses a key and keeps repeating it, with the delay and interval of the
+  // repeat settings, until it is released
+  pub fn hold(&mut self, command: Command) {
+    self.press(command);
+
+    let now = self.time();
+    let mut key_repeat = self.world.write_resource::<KeyRepeat>();
+    key_repeat.held = Some(command);
+    key_repeat.next_repeat_at = now + key_repeat.settings.delay;
+  }
+
+  pub fn release(&mut self, command: Command) {
+    let mut key_repeat = self.world.write_resource::<KeyRepeat>();
+    if key_repeat.held == Some(command) {
+      key_repeat.held = None;
+    }
+  }
+
+  pub fn set_animation_settings(&mut self, settings: AnimationSettings) {
+    self.world.write_resource::<Animation>().settings = settings;
+  }
+
+  pub fn is_animating(&self) -> bool {
+    self.world.read_resource::<Animation>().in_progress
+  }
+
+  // Puts everything straight where it stands in the game
+  pub fn finish_animations(&mut self) {
+    for (position, visual_position) in (&self.world.read_storage::<Position>(), &mut self.world.write_storage::<VisualPosition>()).join() {
+      visual_position.to = (position.x, position.y);
+      visual_position.snap();
+    }
+    self.world.write_resource::<Animation>().in_progress = false;
+  }
+
+  pub fn set_repeat_settings(&mut self, settings: RepeatSettings) {
+    self.world.write_resource::<KeyRepeat>().settings = settings;
+  }
+
+  // Handles a single command right away, returning the events it caused.
+  // Any running animation is skipped so the command isn't held back.
+  pub fn apply(&mut self, command: Command) -> Vec<Event> {
+    self.finish_animations();
+    let queued = QueuedCommand { command, expires_at: None };
+    self.world.write_resource::<InputQueue>().commands.push_front(queued);
+    self.update()
+  }
+
+  pub fn step(&mut self, direction: Direction) -> Vec<Event> {
+    self.apply(Command::Move(direction))
+  }
+
+  pub fn undo(&mut self) -> Vec<Event> {
+    self.apply(Command::Undo)
+  }
+
+  pub fn redo(&mut self) -> Vec<Event> {
+    self.apply(Command::Redo)
+  }
+
+  // Queues the steps of the shortest walk to the given cell, without pushing
+  // any box. Anything still queued is dropped, as the walk starts from where
+  // the player stands now. Returns false when the cell can't be reached
+  // without crossing ice, teleporters, locked doors or gates.
+  pub fn walk_to(&mut self, x: i32, y: i32) -> bool {
+    let (board, state) = Board::from_world(&self.world);
+    let path = board
+      .index(x, y)
+      .filter(|target| !board.is_wall(*target))
+      .and_then(|target| walk_path(&board, &occupancy(&board, &state.boxes), state.player, target));
+
+    match path {
+      Some(path) => {
+        let mut input_queue = self.world.write_resource::<InputQueue>();
+        input_queue.commands.clear();
+        input_queue.extend(path.into_iter().map(Command::Move));
+        true
+      }
+      None => false,
+    }
+  }
+
+  // Selects the box on the given cell to be pushed somewhere by `push_box_to`.
+  // Returns false, clearing the selection, when there is no box there.
+  pub fn select_box_at(&mut self, x: i32, y: i32) -> bool {
+    let selected = {
+      let entities = self.world.entities();
+      let positions = self.world.read_storage::<Position>();
+      let boxes = self.world.read_storage::<Box>();
+      (&entities, &positions, &boxes)
+        .join()
+        .find(|(_, position, _)| position.x == x && position.y == y)
+        .map(|(entity, _, _)| entity.id())
+    };
+
+    self.world.write_resource::<BoxSelection>().selected = selected;
+    selected.is_some()
+  }
+
+  pub fn selected_box(&self) -> Option<EntityId> {
+    self.world.read_resource::<BoxSelection>().selected
+  }
+
+  pub fn clear_selection(&mut self) {
+    self.world.write_resource::<BoxSelection>().selected = None;
+  }
+
+  // Queues the steps that push the selected box onto the given cell, walking
+  // the player around it as needed, and clears the selection. Returns false
+  // when nothing is selected or the box can't get there.
+  pub fn push_box_to(&mut self, x: i32, y: i32) -> bool {
+    let selected = self.world.write_resource::<BoxSelection>().selected.take();
+    let from = selected.and_then(|id| {
+      let positions = self.world.read_storage::<Position>();
+      positions.get(self.world.entities().entity(id)).map(|position| (position.x, position.y))
+    });
+
+    let (board, state) = Board::from_world(&self.world);
+    let path = from
+      .and_then(|(from_x, from_y)| Some((board.index(from_x, from_y)?, board.index(x, y)?)))
+      .and_then(|(from, to)| push_path(&board, &state, from, to));
+
+    match path {
+      Some(solution) => {
+        let mut input_queue = self.world.write_resource::<InputQueue>();
+        input_queue.commands.clear();
+        input_queue.extend(solution.lurd.chars().filter_map(Direction::from_lurd).map(Command::Move));
+        true
+      }
+      None => false,
+    }
+  }
+
+  pub fn advance_time(&mut self, delta: Duration) {
+    self.world.write_resource::<Time>().delta += delta;
+  }
+
+  // How long the game has been running, as seen by the systems
+  pub fn time(&self) -> Duration {
+    self.world.read_resource::<Time>().delta
+  }
+
+  // Plays the given steps on the current level, one every `interval`
+  pub fn replay(&mut self, steps: Vec<Command>, interval: Duration) {
+    let time = self.world.read_resource::<Time>().delta;
+    *self.world.write_resource::<Replay>() = Replay {
+      active: true,
+      steps: steps.into(),
+      interval,
+      next_step_at: time,
+    };
+  }
+
+  // Runs the game rules once: the next queued command is handled, the win
+  // condition is checked and all the events that followed are returned.
+  pub fn update(&mut self) -> Vec<Event> {
+    {
+      let mut repeat_system = RepeatSystem {};
+      repeat_system.run(self.world.system_data());
+    }
+
+    {
+      let mut input_system = InputSystem {};
+      input_system.run(self.world.system_data());
+    }
+
+    {
+      let mut gate_system = GateSystem {};
+      gate_system.run(self.world.system_data());
+    }
+
+    {
+      let mut gss = GameplayStateSystem {};
+      gss.run(self.world.system_data());
+    }
+
+    {
+      let mut replay_system = ReplaySystem {};
+      replay_system.run(self.world.system_data());
+    }
+
+    {
+      let mut event_system = EventSystem {};
+      event_system.run(self.world.system_data());
+    }
+
+    {
+      let mut animation_system = AnimationSystem {};
+      animation_system.run(self.world.system_data());
+    }
+
+    std::mem::take(&mut self.world.write_resource::<EventQueue>().handled)
+  }
+
+  // Works out the next step towards a solution from where the boxes are now,
+  // and keeps it around to be shown. Returns `None` once the level is solved.
+  pub fn hint(&mut self, limits: SolverLimits) -> Result<Option<Hint>, SolverError> {
+    let (board, state) = Board::from_world(&self.world);
+    let solution = solve_board(&board, &state, SolverMode::Pushes, limits)?;
+    Ok(self.show_hint(&solution))
+  }
+
+  // Like `hint`, but the solver runs on its own thread so a long search
+  // doesn't hold up the caller. The result is picked up by `poll_hint`.
+  pub fn request_hint(&mut self, limits: SolverLimits) {
+    let (board, start) = Board::from_world(&self.world);
+    let (sender, receiver) = mpsc::channel();
+    let state = start.clone();
+    thread::spawn(move || {
+      // Nobody is waiting anymore when the receiver is gone
+      let _ = sender.send(solve_board(&board, &state, SolverMode::Pushes, limits));
+    });
+    self.pending_hint = Some(PendingHint { start, receiver });
+  }
+
+  // The hint asked for by `request_hint`, once the solver is done with it.
+  // It is dropped when the level changed in the meantime, as the step it
+  // suggests would no longer fit.
+  pub fn poll_hint(&mut self) -> Option<Result<Option<Hint>, SolverError>> {
+    let result = match self.pending_hint.as_ref()?.receiver.try_recv() {
+      Ok(result) => result,
+      Err(TryRecvError::Empty) => return None,
+      Err(TryRecvError::Disconnected) => {
+        self.pending_hint = None;
+        return None;
+      }
+    };
+
+    let start = self.pending_hint.take()?.start;
+    if Board::from_world(&self.world).1 != start {
+      return None;
+    }
+    Some(result.map(|solution| self.show_hint(&solution)))
+  }
+
+  // Keeps the first step of the solution around to be shown
+  fn show_hint(&mut self, solution: &Solution) -> Option<Hint> {
+    let hint = solution.lurd.chars().next().and_then(Direction::from_lurd).map(|direction| {
+      let pushed_box = if solution.lurd.starts_with(|step: char| step.is_ascii_uppercase()) {
+        self.entity_next_to_player::<Box>(direction)
+      } else {
+        None
+      };
+      Hint { direction, pushed_box }
+    });
+
+    self.world.write_resource::<CurrentHint>().hint = hint;
+    hint
+  }
+
+  pub fn current_hint(&self) -> Option<Hint> {
+    self.world.read_resource::<CurrentHint>().hint
+  }
+
+  // The entity with a `T` component on the cell next to the player
+  fn entity_next_to_player<T: Component>(&self, direction: Direction) -> Option<EntityId> {
+    let (x, y) = self.player_position()?;
+    let (dx, dy) = direction.delta();
+    let entities = self.world.entities();
+    let positions = self.world.read_storage::<Position>();
+    let storage = self.world.read_storage::<T>();
+    (&entities, &positions, &storage)
+      .join()
+      .find(|(_, position, _)| position.x == x + dx && position.y == y + dy)
+      .map(|(entity, _, _)| entity.id())
+  }
+
+  pub fn state(&self) -> GameplayState {
+    self.world.read_resource::<Gameplay>().state
+  }
+
+  pub fn is_level_completed(&self) -> bool {
+    self.world.read_resource::<CurrentLevel>().completed
+  }
+
+  pub fn moves_count(&self) -> u32 {
+    self.world.read_resource::<Gameplay>().moves_count
+  }
+
+  pub fn pushes_count(&self) -> u32 {
+    self.world.read_resource::<Gameplay>().pushes_count
+  }
+
+  // All the steps taken on the current level, as a LURD string
+  pub fn lurd(&self) -> String {
+    self.world.read_resource::<MoveHistory>().lurd()
+  }
+
+  pub fn player_position(&self) -> Option<(i32, i32)> {
+    let positions = self.world.read_storage::<Position>();
+    let players = self.world.read_storage::<Player>();
+    (&positions, &players).join().map(|(position, _)| (position.x, position.y)).next()
+  }
+
+  pub fn box_positions(&self) -> Vec<(i32, i32)> {
+    let positions = self.world.read_storage::<Position>();
+    let boxes = self.world.read_storage::<Box>();
+    (&positions, &boxes).join().map(|(position, _)| (position.x, position.y)).collect()
+  }
+}
@@ -0,0 +1,572 @@
+use std::{cmp::Reverse, collections::{BinaryHeap, HashMap, VecDeque}, fmt::{self, Display}, time::{Duration, Instant}};
+
+use crate::{board::{Board, BoardState, Mechanic, DIRECTIONS}, components::{BoxColor, Direction}, maps::Level};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SolverMode {
+  // Fewest pushes, then whatever number of moves that takes
+  Pushes,
+  // Fewest moves overall
+  Moves,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SolverLimits {
+  pub max_time: Duration,
+  // How many distinct positions may be kept in memory
+  pub max_states: usize,
+}
+
+impl Default for SolverLimits {
+  fn default() -> Self {
+    SolverLimits {
+      max_time: Duration::from_secs(10),
+      max_states: 2_000_000,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Solution {
+  pub lurd: String,
+  pub moves: usize,
+  pub pushes: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SolverError {
+  Unsolvable,
+  TimedOut,
+  TooManyStates,
+  Unsupported(Mechanic),
+}
+
+impl Display for SolverError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SolverError::Unsolvable => write!(f, "level has no solution"),
+      SolverError::TimedOut => write!(f, "solver ran out of time"),
+      SolverError::TooManyStates => write!(f, "solver ran out of memory"),
+      SolverError::Unsupported(mechanic) => write!(f, "solver can't handle levels with {mechanic}"),
+    }
+  }
+}
+
+impl std::error::Error for SolverError {}
+
+struct Node {
+  boxes: Vec<(usize, BoxColor)>,
+  // Where the player really stands, right after the push that led here
+  player: usize,
+  parent: Option<usize>,
+  // The box that was pushed to get here and the direction of the push
+  push: Option<(usize, Direction)>,
+}
+
+struct Search<'a> {
+  board: &'a Board,
+  mode: SolverMode,
+  // Push distances to the nearest spot, per box color
+  distances: HashMap<BoxColor, Vec<Option<u32>>>,
+  // Push distances to each single spot, per box color
+  spot_distances: HashMap<BoxColor, Vec<Vec<Option<u32>>>>,
+}
+
+impl<'a> Search<'a> {
+  fn new(board: &'a Board, boxes: &[(usize, BoxColor)], mode: SolverMode) -> Self {
+    let mut distances = HashMap::new();
+    let mut spot_distances = HashMap::new();
+    for (_, color) in boxes {
+      distances.entry(*color).or_insert_with(|| board.push_distances(*color));
+      spot_distances.entry(*color).or_insert_with(|| {
+        board.spots_of(*color).into_iter().map(|spot| board.push_distances_to(spot)).collect()
+      });
+    }
+    Search { board, mode, distances, spot_distances }
+  }
+
+  // Every box needs its own spot, so the cheapest way of sharing the spots
+  // out, counting the pushes each box needs to get to its spot, is a lower
+  // bound. Each push is a move, so this never overestimates either cost.
+  fn heuristic(&self, boxes: &[(usize, BoxColor)]) -> Option<u32> {
+    let mut total = 0;
+    for (color, spots) in self.spot_distances.iter() {
+      let costs: Vec<Vec<Option<u32>>> = boxes
+        .iter()
+        .filter(|(_, box_color)| box_color == color)
+        .map(|(index, _)| spots.iter().map(|distances| distances[*index]).collect())
+        .collect();
+      total += min_assignment(&costs)?;
+    }
+    Some(total)
+  }
+
+  fn is_solved(&self, boxes: &[(usize, BoxColor)]) -> bool {
+    boxes.iter().all(|(index, color)| self.board.spot(*index) == Some(*color))
+  }
+
+  // A box pushed into a 2x2 block of walls and boxes can never move again,
+  // which is only fine if every box in the block is already home.
+  fn is_frozen(&self, occupied: &[Option<BoxColor>], index: usize) -> bool {
+    let (x, y) = self.board.coords(index);
+    for (dx, dy) in [(-1, -1), (0, -1), (-1, 0), (0, 0)] {
+      let square = [(x + dx, y + dy), (x + dx + 1, y + dy), (x + dx, y + dy + 1), (x + dx + 1, y + dy + 1)];
+      let mut blocked = true;
+      let mut misplaced = false;
+
+      for (sx, sy) in square {
+        match self.board.index(sx, sy) {
+          Some(cell) if self.board.is_wall(cell) => (),
+          Some(cell) => match occupied[cell] {
+            Some(color) => misplaced |= self.board.spot(cell) != Some(color),
+            None => {
+              blocked = false;
+              break;
+            }
+          },
+          None => (),
+        }
+      }
+
+      if blocked && misplaced {
+        return true;
+      }
+    }
+    false
+  }
+
+  // Where the player would stand in the state key: pushes only care about
+  // the area the player can reach, moves need the exact cell.
+  fn key_player(&self, reach: &[Option<u32>], player: usize) -> usize {
+    match self.mode {
+      SolverMode::Pushes => reach.iter().position(|distance| distance.is_some()).unwrap_or(player),
+      SolverMode::Moves => player,
+    }
+  }
+
+  fn solve(&self, start: BoardState, limits: SolverLimits) -> Result<Vec<(usize, Direction)>, SolverError> {
+    let started = Instant::now();
+    let mut boxes = start.boxes;
+    boxes.sort_unstable();
+
+    let estimate = self.heuristic(&boxes).ok_or(SolverError::Unsolvable)?;
+    let reach = walk_distances(self.board, &occupancy(self.board, &boxes), start.player);
+
+    // Cheapest known cost of every state seen so far
+    let mut best: HashMap<(Vec<(usize, BoxColor)>, usize), u32> = HashMap::new();
+    best.insert((boxes.clone(), self.key_player(&reach, start.player)), 0);
+
+    let mut nodes = vec![Node { boxes, player: start.player, parent: None, push: None }];
+    // Ties on the estimated total go to the deepest node, which is closer to a solution
+    let mut open = BinaryHeap::new();
+    open.push((Reverse(estimate), 0, Reverse(0)));
+    let mut expanded: usize = 0;
+
+    while let Some((_, cost, Reverse(node_index))) = open.pop() {
+      if self.is_solved(&nodes[node_index].boxes) {
+        return Ok(pushes_to(&nodes, node_index));
+      }
+
+      let occupied = occupancy(self.board, &nodes[node_index].boxes);
+      let reach = walk_distances(self.board, &occupied, nodes[node_index].player);
+
+      // A cheaper way to this state may have been found since it was queued,
+      // and that node is expanded instead
+      let key = (nodes[node_index].boxes.clone(), self.key_player(&reach, nodes[node_index].player));
+      if best.get(&key).is_some_and(|known| *known < cost) {
+        continue;
+      }
+
+      expanded += 1;
+      if expanded.is_multiple_of(1024) && started.elapsed() > limits.max_time {
+        return Err(SolverError::TimedOut);
+      }
+
+      for (box_number, (box_index, color)) in nodes[node_index].boxes.clone().into_iter().enumerate() {
+        for direction in DIRECTIONS {
+          // The player pushes from the opposite side of the box
+          let behind = match self.board.floor_neighbor(box_index, opposite(direction)) {
+            Some(behind) => behind,
+            None => continue,
+          };
+          let walk = match reach[behind] {
+            Some(walk) => walk,
+            None => continue,
+          };
+          let target = match self.board.floor_neighbor(box_index, direction) {
+            Some(target) if occupied[target].is_none() => target,
+            _ => continue,
+          };
+          if self.distances[&color][target].is_none() {
+            continue;
+          }
+
+          let mut next_occupied = occupied.clone();
+          next_occupied[box_index] = None;
+          next_occupied[target] = Some(color);
+          if self.is_frozen(&next_occupied, target) {
+            continue;
+          }
+
+          let mut next_boxes = nodes[node_index].boxes.clone();
+          next_boxes[box_number] = (target, color);
+          next_boxes.sort_unstable();
+
+          let estimate = match self.heuristic(&next_boxes) {
+            Some(estimate) => estimate,
+            None => continue,
+          };
+          let next_cost = cost + match self.mode {
+            SolverMode::Pushes => 1,
+            SolverMode::Moves => walk + 1,
+          };
+
+          let next_reach = walk_distances(self.board, &next_occupied, box_index);
+          let key = (next_boxes, self.key_player(&next_reach, box_index));
+          if best.get(&key).is_some_and(|known| *known <= next_cost) {
+            continue;
+          }
+          if best.len() >= limits.max_states {
+            return Err(SolverError::TooManyStates);
+          }
+          best.insert(key.clone(), next_cost);
+
+          nodes.push(Node {
+            boxes: key.0,
+            player: box_index,
+            parent: Some(node_index),
+            push: Some((box_index, direction)),
+          });
+          open.push((Reverse(next_cost + estimate), next_cost, Reverse(nodes.len() - 1)));
+        }
+      }
+    }
+
+    Err(SolverError::Unsolvable)
+  }
+}
+
+// The least total cost of giving every row its own column, using the
+// Hungarian method. `None` costs can't be used, and if there is no way
+// around them there is no assignment at all.
+fn min_assignment(costs: &[Vec<Option<u32>>]) -> Option<u32> {
+  const UNREACHABLE: i64 = 1 << 40;
+
+  let rows = costs.len();
+  let columns = costs.first().map_or(0, Vec::len);
+  if rows == 0 {
+    return Some(0);
+  }
+  if rows > columns {
+    return None;
+  }
+
+  let cost = |row: usize, column: usize| costs[row][column].map_or(UNREACHABLE, i64::from);
+
+  // Potentials and matching are 1-based, with 0 as a virtual starting column
+  let mut row_potential = vec![0i64; rows + 1];
+  let mut column_potential = vec![0i64; columns + 1];
+  let mut column_row = vec![0usize; columns + 1];
+  let mut way = vec![0usize; columns + 1];
+
+  for row in 1..=rows {
+    column_row[0] = row;
+    let mut column = 0;
+    let mut min_slack = vec![i64::MAX; columns + 1];
+    let mut used = vec![false; columns + 1];
+
+    loop {
+      used[column] = true;
+      let current_row = column_row[column];
+      let mut delta = i64::MAX;
+      let mut next_column = 0;
+
+      for candidate in 1..=columns {
+        if used[candidate] {
+          continue;
+        }
+        let slack = cost(current_row - 1, candidate - 1) - row_potential[current_row] - column_potential[candidate];
+        if slack < min_slack[candidate] {
+          min_slack[candidate] = slack;
+          way[candidate] = column;
+        }
+        if min_slack[candidate] < delta {
+          delta = min_slack[candidate];
+          next_column = candidate;
+        }
+      }
+
+      for candidate in 0..=columns {
+        if used[candidate] {
+          row_potential[column_row[candidate]] += delta;
+          column_potential[candidate] -= delta;
+        } else {
+          min_slack[candidate] -= delta;
+        }
+      }
+
+      column = next_column;
+      if column_row[column] == 0 {
+        break;
+      }
+    }
+
+    loop {
+      let previous = way[column];
+      column_row[column] = column_row[previous];
+      column = previous;
+      if column == 0 {
+        break;
+      }
+    }
+  }
+
+  let total: i64 = column_row
+    .iter()
+    .enumerate()
+    .skip(1)
+    .filter(|(_, row)| **row != 0)
+    .map(|(column, row)| cost(row - 1, column - 1))
+    .sum();
+
+  if total >= UNREACHABLE {
+    None
+  } else {
+    Some(total as u32)
+  }
+}
+
+fn opposite(direction: Direction) -> Direction {
+  match direction {
+    Direction::Up => Direction::Down,
+    Direction::Down => Direction::Up,
+    Direction::Left => Direction::Right,
+    Direction::Right => Direction::Left,
+  }
+}
+
+pub(crate) fn occupancy(board: &Board, boxes: &[(usize, BoxColor)]) -> Vec<Option<BoxColor>> {
+  let mut occupied = vec![None; board.len()];
+  for (index, color) in boxes {
+    occupied[*index] = Some(*color);
+  }
+  occupied
+}
+
+// Number of steps for the player to reach every cell without pushing anything
+fn walk_distances(board: &Board, occupied: &[Option<BoxColor>], player: usize) -> Vec<Option<u32>> {
+  let mut distances = vec![None; board.len()];
+  let mut queue = VecDeque::from([player]);
+  distances[player] = Some(0);
+
+  while let Some(index) = queue.pop_front() {
+    let distance = distances[index].unwrap_or(0);
+    for direction in DIRECTIONS {
+      if let Some(next) = board.floor_neighbor(index, direction) {
+        if occupied[next].is_none() && distances[next].is_none() {
+          distances[next] = Some(distance + 1);
+          queue.push_back(next);
+        }
+      }
+    }
+  }
+
+  distances
+}
+
+// The shortest walk from `from` to `to` that doesn't push anything
+pub(crate) fn walk_path(board: &Board, occupied: &[Option<BoxColor>], from: usize, to: usize) -> Option<Vec<Direction>> {
+  let mut came_from: Vec<Option<(usize, Direction)>> = vec![None; board.len()];
+  let mut queue = VecDeque::from([from]);
+  let mut visited = vec![false; board.len()];
+  visited[from] = true;
+
+  while let Some(index) = queue.pop_front() {
+    if index == to {
+      let mut path = Vec::new();
+      let mut current = to;
+      while let Some((previous, direction)) = came_from[current] {
+        path.push(direction);
+        current = previous;
+      }
+      path.reverse();
+      return Some(path);
+    }
+
+    for direction in DIRECTIONS {
+      if let Some(next) = board.floor_neighbor(index, direction) {
+        if occupied[next].is_none() && !visited[next] {
+          visited[next] = true;
+          came_from[next] = Some((index, direction));
+          queue.push_back(next);
+        }
+      }
+    }
+  }
+
+  None
+}
+
+fn pushes_to(nodes: &[Node], node_index: usize) -> Vec<(usize, Direction)> {
+  let mut pushes = Vec::new();
+  let mut current = Some(node_index);
+  while let Some(index) = current {
+    if let Some(push) = nodes[index].push {
+      pushes.push(push);
+    }
+    current = nodes[index].parent;
+  }
+  pushes.reverse();
+  pushes
+}
+
+// Turns a list of pushes back into every single step, walking the player
+// between pushes along the shortest path.
+fn to_solution(board: &Board, start: &BoardState, pushes: &[(usize, Direction)]) -> Solution {
+  let mut occupied = occupancy(board, &start.boxes);
+  let mut player = start.player;
+  let mut lurd = String::new();
+
+  for (box_index, direction) in pushes {
+    let behind = board.neighbor(*box_index, opposite(*direction)).unwrap_or(player);
+    let walk = walk_path(board, &occupied, player, behind).unwrap_or_default();
+    lurd.extend(walk.into_iter().map(Direction::lurd));
+    lurd.push(direction.lurd().to_ascii_uppercase());
+
+    if let Some(target) = board.neighbor(*box_index, *direction) {
+      occupied[target] = occupied[*box_index].take();
+    }
+    player = *box_index;
+  }
+
+  Solution { moves: lurd.len(), pushes: pushes.len(), lurd }
+}
+
+/*
+求解关卡，返回推动次数或移动次数最少的 LURD 解法
+*/
+pub fn solve(level: &Level, mode: SolverMode, limits: SolverLimits) -> Result<Solution, SolverError> {
+  let (board, start) = Board::from_level(level);
+  solve_board(&board, &start, mode, limits)
+}
+
+pub(crate) fn solve_board(board: &Board, start: &BoardState, mode: SolverMode, limits: SolverLimits) -> Result<Solution, SolverError> {
+  // Solutions are only found when they keep clear of these, and when there
+  // are none the level would wrongly be reported unsolvable. Ice and
+  // teleporters are there to be used, so those levels aren't even tried,
+  // but doors and gates may well be out of the way.
+  if let Some(mechanic) = [Mechanic::Ice, Mechanic::Teleporters].into_iter().find(|mechanic| board.has(*mechanic)) {
+    return Err(SolverError::Unsupported(mechanic));
+  }
+  let search = Search::new(board, &start.boxes, mode);
+  let pushes = match search.solve(start.clone(), limits) {
+    Err(SolverError::Unsolvable) if board.has(Mechanic::Locks) => return Err(SolverError::Unsupported(Mechanic::Locks)),
+    result => result?,
+  };
+  Ok(to_solution(board, start, &pushes))
+}
+
+// The fewest pushes that take the box on `from` to `to` while every other
+// box stays where it is, with the walks in between
+pub(crate) fn push_path(board: &Board, start: &BoardState, from: usize, to: usize) -> Option<Solution> {
+  let mut occupied = occupancy(board, &start.boxes);
+  let color = occupied[from].take();
+
+  // Every state is where the box is and where the player stands, which after
+  // a push is always the cell the box just left
+  let mut visited = HashMap::new();
+  visited.insert((from, start.player), None::<((usize, usize), Direction)>);
+  let mut queue = VecDeque::from([(from, start.player)]);
+
+  while let Some((box_index, player)) = queue.pop_front() {
+    if box_index == to {
+      let mut pushes = Vec::new();
+      let mut current = (box_index, player);
+      while let Some(Some((previous, direction))) = visited.get(&current) {
+        pushes.push((previous.0, *direction));
+        current = *previous;
+      }
+      pushes.reverse();
+      return Some(to_solution(board, start, &pushes));
+    }
+
+    occupied[box_index] = color;
+    let reach = walk_distances(board, &occupied, player);
+    occupied[box_index] = None;
+
+    for direction in DIRECTIONS {
+      let behind = board.neighbor(box_index, opposite(direction));
+      let target = board.floor_neighbor(box_index, direction).filter(|target| occupied[*target].is_none());
+      if let (Some(behind), Some(target)) = (behind, target) {
+        let next = (target, box_index);
+        if reach[behind].is_some() && !visited.contains_key(&next) {
+          visited.insert(next, Some(((box_index, player), direction)));
+          queue.push_back(next);
+        }
+      }
+    }
+  }
+
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::level_pack::LevelPack;
+
+  fn default_levels() -> LevelPack {
+    include_str!("../resources/levels/default.xsb").parse().expect("expected default levels")
+  }
+
+  #[test]
+  fn finds_fewest_pushes() {
+    let pushes: Vec<usize> = default_levels()
+      .levels()
+      .iter()
+      .map(|level| solve(level, SolverMode::Pushes, SolverLimits::default()).expect("expected solution").pushes)
+      .collect();
+    assert_eq!(pushes, [8, 5, 3]);
+  }
+
+  #[test]
+  fn finds_fewest_moves() {
+    let counts: Vec<(usize, usize)> = default_levels()
+      .levels()
+      .iter()
+      .map(|level| solve(level, SolverMode::Moves, SolverLimits::default()).expect("expected solution"))
+      .map(|solution| (solution.moves, solution.pushes))
+      .collect();
+    assert_eq!(counts, [(16, 8), (12, 5), (15, 3)]);
+  }
+
+  #[test]
+  fn solution_matches_counts() {
+    let levels = default_levels();
+    let solution = solve(&levels.levels()[0], SolverMode::Moves, SolverLimits::default()).expect("expected solution");
+    assert_eq!(solution.lurd.len(), solution.moves);
+    assert_eq!(solution.lurd.chars().filter(char::is_ascii_uppercase).count(), solution.pushes);
+  }
+
+  #[test]
+  fn box_in_corner_is_unsolvable() {
+    let level: Level = "#####\n#$ .#\n# @ #\n#####".parse().expect("expected level");
+    assert!(matches!(solve(&level, SolverMode::Pushes, SolverLimits::default()), Err(SolverError::Unsolvable)));
+  }
+
+  #[test]
+  fn assigns_cheapest_columns() {
+    let costs = vec![
+      vec![Some(4), Some(1), Some(3)],
+      vec![Some(2), Some(0), Some(5)],
+      vec![Some(3), Some(2), Some(2)],
+    ];
+    assert_eq!(min_assignment(&costs), Some(5));
+  }
+
+  #[test]
+  fn assignment_avoids_unusable_costs() {
+    assert_eq!(min_assignment(&[vec![Some(1), Some(5)], vec![Some(1), None]]), Some(6));
+    assert_eq!(min_assignment(&[vec![Some(1), None], vec![Some(2), None]]), None);
+  }
+}
@@ -0,0 +1,70 @@
+use std::collections::HashSet;
+
+use specs::{Entities, Join, Read, ReadStorage, System, Write, WriteStorage};
+
+use crate::{components::{Position, VisualPosition}, events::{Event, Teleported}, resources::{Animation, AnimationInput, EventQueue, Time}};
+
+pub struct AnimationSystem {}
+
+impl<'a> System<'a> for AnimationSystem {
+  type SystemData = (
+    Write<'a, Animation>,
+    Read<'a, Time>,
+    Read<'a, EventQueue>,
+    Entities<'a>,
+    ReadStorage<'a, Position>,
+    WriteStorage<'a, VisualPosition>,
+  );
+
+  fn run(&mut self, data: Self::SystemData) {
+    let (mut animation, time, event_queue, entities, positions, mut visual_positions) = data;
+    let settings = animation.settings;
+    let mut in_progress = false;
+
+    // Gliding across the board would hide that these went through a
+    // teleporter, so they appear at the exit straight away
+    let teleported: HashSet<u32> = event_queue
+      .handled
+      .iter()
+      .filter_map(|event| match event {
+        Event::Teleported(Teleported { id, .. }) => Some(*id),
+        _ => None,
+      })
+      .collect();
+
+    for (entity, position, visual_position) in (&entities, &positions, &mut visual_positions).join() {
+      let target = (position.x, position.y);
+      if teleported.contains(&entity.id()) {
+        visual_position.to = target;
+        visual_position.snap();
+      } else if visual_position.to != target {
+        // The entity moved: glide from where it is drawn now, or from where
+        // the last animation was going when that one is cut short
+        if settings.input == AnimationInput::FastForward {
+          visual_position.snap();
+        } else {
+          visual_position.from = (visual_position.x, visual_position.y);
+        }
+        visual_position.to = target;
+        visual_position.started_at = time.delta;
+      }
+
+      let elapsed = time.delta.saturating_sub(visual_position.started_at);
+      let progress = if elapsed >= settings.duration {
+        1.0
+      } else {
+        elapsed.as_secs_f32() / settings.duration.as_secs_f32()
+      };
+
+      // Ease out, so steps settle into place
+      let eased = progress * (2.0 - progress);
+      let (from_x, from_y) = visual_position.from;
+      visual_position.x = from_x + (target.0 as f32 - from_x) * eased;
+      visual_position.y = from_y + (target.1 as f32 - from_y) * eased;
+
+      in_progress |= progress < 1.0;
+    }
+
+    animation.in_progress = in_progress;
+  }
+}
@@ -1,79 +1,92 @@
 use std::collections::HashMap;
 
-use ggez::Context;
-use specs::{Entities, Join, Read, ReadStorage, System, Write};
+use specs::{Entities, Join, Read, ReadStorage, System, Write, WriteStorage};
 
-use crate::{components::{Box, BoxSpot, Position}, events::{BoxPlacedOnSpot, EntityMoved, Event}, resources::{AudioStore, EventQueue, Gameplay, GameplayState}};
+use crate::{board::Board, components::{Box, BoxSpot, Position, Stuck, Teleporter, Wall}, deadlock::DeadlockDetector, events::{BoxPlacedOnSpot, Deadlock, EntityMoved, Event}, resources::{CurrentLevel, EventQueue, Gameplay, GameplayState, MapBounds, RuleSet}};
 
-pub struct EventSystem<'a>{
-  pub context: &'a mut Context,
-}
+pub struct EventSystem {}
 
-impl<'a> System<'a> for EventSystem<'a> {
+impl<'a> System<'a> for EventSystem {
     // Data
   type SystemData = (
     Write<'a, EventQueue>,
-    Write<'a, AudioStore>,
     Read<'a, Gameplay>,
+    Write<'a, CurrentLevel>,
+    Read<'a, MapBounds>,
+    Read<'a, RuleSet>,
     Entities<'a>,
     ReadStorage<'a, Position>,
     ReadStorage<'a, Box>,
     ReadStorage<'a, BoxSpot>,
+    ReadStorage<'a, Wall>,
+    ReadStorage<'a, Teleporter>,
+    WriteStorage<'a, Stuck>,
   );
 
   fn run(&mut self, data: Self::SystemData) {
-    let (mut event_queue, mut audio_store, gameplay, entities, positions, boxes, box_spots) = data;
+    let (mut event_queue, gameplay, mut current_level, map_bounds, rules, entities, positions, boxes, box_spots, walls, teleporters, mut stuck) = data;
 
-    let mut new_events = Vec::new();
+    // Events fired while handling others are handled in the same run, so
+    // whoever drives the game sees the whole chain at once.
+    loop {
+      let events = std::mem::take(&mut event_queue.events);
+      if events.is_empty() {
+        break;
+      }
 
-    for event in event_queue.events.drain(..) {
-      println!("New event: {:?}", event);
+      for event in events {
+        match event {
+          Event::EntityMoved(EntityMoved { id }) => {
+            // An entity was just moved, check if it was a box and fire
+            // more events if it's been moved on a spot.
+            if let Some(the_box) = boxes.get(entities.entity(id)) {
+              let box_spots_with_positions: HashMap<(i32, i32), &BoxSpot> =
+                  (&box_spots, &positions)
+                      .join()
+                      .map(|t| ((t.1.x, t.1.y), t.0))
+                      .collect::<HashMap<_, _>>();
 
-      match event { 
-        Event::PlayerHitObstacle => {
-          // play sound here
-          audio_store.play_sound(self.context, &"wall".to_string());
-        }
-        Event::EntityMoved(EntityMoved { id }) => {
-          // An entity was just moved, check if it was a box and fire
-          // more events if it's been moved on a spot.
-          if let Some(the_box) = boxes.get(entities.entity(id)) {
-            let box_spots_with_positions: HashMap<(u8, u8), &BoxSpot> =
-                (&box_spots, &positions)
-                    .join()
-                    .map(|t| ((t.1.x, t.1.y), t.0))
-                    .collect::<HashMap<_, _>>();
+              if let Some(box_position) = positions.get(entities.entity(id)) {
+                // Check if there is a spot on this position, and if there
+                // is if it's the correct or incorrect type
+                if let Some(box_spot) = box_spots_with_positions.get(&(box_position.x, box_position.y))
+                {
+                  event_queue.events.push(Event::BoxPlacedOnSpot(BoxPlacedOnSpot {
+                    is_correct_spot: (box_spot.color == the_box.color),
+                  }));
+                }
+              }
+
+              // Boxes moving can get others stuck or free them again (undo
+              // does), so every box is looked at again. Doors and gates can
+              // still open, so only walls can get a box stuck. Teleporters
+              // take boxes where pushing can't, which rules out dead squares.
+              let board = Board::from_storages(&map_bounds, &positions, &walls, &box_spots);
+              let box_cells: Vec<_> = (&entities, &positions, &boxes)
+                .join()
+                .filter_map(|(entity, position, the_box)| Some((entity, board.index(position.x, position.y)?, the_box.color)))
+                .collect();
+              let detector = DeadlockDetector::new(&board, &box_cells.iter().map(|(_, cell, color)| (*cell, *color)).collect::<Vec<_>>(), *rules, teleporters.join().next().is_some());
 
-            if let Some(box_position) = positions.get(entities.entity(id)) {
-              // Check if there is a spot on this position, and if there
-              // is if it's the correct or incorrect type
-              if let Some(box_spot) = box_spots_with_positions.get(&(box_position.x, box_position.y))
-              {
-                new_events.push(Event::BoxPlacedOnSpot(BoxPlacedOnSpot {
-                  is_correct_spot: (box_spot.color == the_box.color),
-                }));
+              stuck.clear();
+              for (entity, cell, _) in box_cells.iter() {
+                if let Some(kind) = detector.check(*cell) {
+                  stuck.insert(*entity, Stuck).expect("expected stuck box");
+                  if entity.id() == id {
+                    event_queue.events.push(Event::Deadlock(Deadlock { id, kind }));
+                  }
+                }
               }
             }
           }
-        }
-        Event::BoxPlacedOnSpot(BoxPlacedOnSpot { is_correct_spot }) => {
-          // play sound here
-          if is_correct_spot {
-            audio_store.play_sound(self.context, &"correct".to_string());
-          } else {
-            audio_store.play_sound(self.context, &"incorrect".to_string()); 
-          }
-        }
-        Event::GameOver => {
-          let game_state = &gameplay.state;
-          if *game_state == GameplayState::Won {
-            println!("You win!");
-            self.context.request_quit();
+          Event::GameOver if gameplay.state == GameplayState::Won => {
+            current_level.completed = true;
           }
+          _ => (),
         }
+
+        event_queue.handled.push(event);
       }
     }
-
-    event_queue.events.append(&mut new_events);
   }
-}
\ No newline at end of file
+}
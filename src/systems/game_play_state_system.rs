@@ -1,8 +1,6 @@
-use std::collections::HashMap;
+use specs::{Join, Read, ReadStorage, System, Write};
 
-use specs::{Join, ReadStorage, System, Write};
-
-use crate::{components::{Box, BoxSpot, Position}, events::Event, resources::{EventQueue, Gameplay, GameplayState}};
+use crate::{components::{Box, BoxSpot, Position}, events::Event, resources::{EventQueue, Gameplay, GameplayState, MapBounds}};
 
 pub struct GameplayStateSystem {}
 
@@ -10,32 +8,36 @@ impl<'a> System<'a> for GameplayStateSystem {
   type SystemData = (
     Write<'a, EventQueue>,
     Write<'a, Gameplay>,
+    Read<'a, MapBounds>,
     ReadStorage<'a, Position>,
     ReadStorage<'a, Box>,
     ReadStorage<'a, BoxSpot>,
   );
 
   fn run(&mut self, data: Self::SystemData) {
-    let (mut event_queue, mut game_play, postions, boxes, boxspots) = data;
+    let (mut event_queue, mut game_play, map_bounds, postions, boxes, boxspots) = data;
 
-    let box_positions: HashMap<(u8,u8), &Box> = (&postions, &boxes).join().map(|t| ((t.0.x, t.0.y), t.1)).collect();
+    // One slot per tile of the map, holding the box standing on it
+    let mut box_positions: Vec<Option<&Box>> = vec![None; map_bounds.area()];
+    for (position, bx) in (&postions, &boxes).join() {
+      if let Some(index) = map_bounds.index(position.x, position.y) {
+        box_positions[index] = Some(bx);
+      }
+    }
 
     for (position, boxspot) in (&postions, &boxspots).join() {
-      let match_box = box_positions.get(&(position.x, position.y));
-      
-      if let Some(bx) = match_box {
-        if bx.color == boxspot.color {
-          continue;
-        } else {
+      let match_box = map_bounds.index(position.x, position.y).and_then(|index| box_positions[index]);
+
+      match match_box {
+        Some(bx) if bx.color == boxspot.color => continue,
+        _ => {
+          game_play.state = GameplayState::Playing;
           return;
         }
-      } else {
-        game_play.state = GameplayState::Playing;
-        return;
       }
     }
 
     game_play.state = GameplayState::Won;
     event_queue.events.push(Event::GameOver);
   }
-}
\ No newline at end of file
+}
@@ -0,0 +1,59 @@
+use std::collections::HashSet;
+
+use specs::{Entities, Join, ReadStorage, System, Write, WriteStorage};
+
+use crate::{components::{Gate, Immovable, Movable, Plate, Position, Renderable}, events::{Event, GateChanged, PlateChanged}, resources::EventQueue};
+
+pub struct GateSystem {}
+
+impl<'a> System<'a> for GateSystem {
+  type SystemData = (
+    Write<'a, EventQueue>,
+    Entities<'a>,
+    ReadStorage<'a, Position>,
+    ReadStorage<'a, Movable>,
+    WriteStorage<'a, Plate>,
+    WriteStorage<'a, Gate>,
+    WriteStorage<'a, Immovable>,
+    WriteStorage<'a, Renderable>,
+  );
+
+  fn run(&mut self, data: Self::SystemData) {
+    let (mut event_queue, entities, positions, movables, mut plates, mut gates, mut immovables, mut renderables) = data;
+
+    // The player and boxes hold down the plates they stand on
+    let occupied: HashSet<(i32, i32)> = (&positions, &movables).join().map(|(position, _)| (position.x, position.y)).collect();
+
+    // A level with gates always has plates, but gates never open without any
+    let mut any_plate = false;
+    let mut all_pressed = true;
+    for (entity, position, plate) in (&entities, &positions, &mut plates).join() {
+      let pressed = occupied.contains(&(position.x, position.y));
+      if pressed != plate.pressed {
+        plate.pressed = pressed;
+        event_queue.events.push(Event::PlateChanged(PlateChanged { id: entity.id(), pressed }));
+      }
+      any_plate = true;
+      all_pressed &= pressed;
+    }
+    let all_pressed = any_plate && all_pressed;
+
+    // Gates open while every plate is pressed. A gate never closes on
+    // whatever stands in it, it waits for the way to be clear.
+    for (entity, position, gate) in (&entities, &positions, &mut gates).join() {
+      let open = all_pressed || (gate.open && occupied.contains(&(position.x, position.y)));
+      if open == gate.open {
+        continue;
+      }
+
+      gate.open = open;
+      if open {
+        immovables.remove(entity);
+      } else {
+        immovables.insert(entity, Immovable {}).expect("expected closed gate");
+      }
+      renderables.insert(entity, Renderable::new_static(gate.image_path())).expect("expected gate image");
+      event_queue.events.push(Event::GateChanged(GateChanged { id: entity.id(), open }));
+    }
+  }
+}
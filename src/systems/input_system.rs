@@ -1,102 +1,367 @@
-use std::collections::HashMap;
+use std::{collections::{HashMap, HashSet}, time::Duration};
 
-use ggez::input::keyboard::KeyCode;
-use specs::{world::Index, Entities, Join, ReadStorage, System, Write, WriteStorage};
+use specs::{world::Index, Entities, Join, Read, ReadStorage, System, Write, WriteStorage};
 
-use crate::{components::{Immovable, Movable, Player, Position}, constants::{MAP_HEIGHT, MAP_WIDTH}, events::{EntityMoved, Event}, resources::{EventQueue, Gameplay, InputQueue}};
+use crate::{components::{Door, Ice, Immovable, Key, Movable, Player, PlayerState, Position, Teleporter}, events::{DoorUnlocked, EntityMoved, Event, KeyPickedUp, Teleported}, resources::{Animation, AnimationInput, Command, CurrentHint, Effect, EntityMove, EventQueue, Gameplay, InputQueue, Keyring, MapBounds, MoveHistory, MoveRecord, RuleSet, Time}};
 
 pub struct InputSystem {}
 
+// The tiles that move whatever lands on them further
+struct Tiles {
+  ice: HashSet<(i32, i32)>,
+  // Every teleporter with the one it sends to
+  exits: HashMap<(i32, i32), (i32, i32)>,
+}
+
+// Where an entity ends up after a step
+struct Destination {
+  position: (i32, i32),
+  teleported: bool,
+}
+
+impl InputSystem {
+  // Puts every entity of a recorded step back where it was (or where it went,
+  // when redoing) and fires the same events a normal move would.
+  fn replay_record(record: &MoveRecord, undo: bool, entities: &Entities, positions: &mut WriteStorage<Position>, keyring: &mut Keyring, event_queue: &mut EventQueue) {
+    if undo {
+      for effect in record.effects.iter().rev() {
+        InputSystem::undo_effect(effect, entities, positions, keyring);
+      }
+    } else {
+      for effect in record.effects.iter() {
+        InputSystem::apply_effect(effect, entities, positions, keyring, event_queue);
+      }
+    }
+
+    for entity_move in record.moves.iter() {
+      let (from, to) = if undo { (entity_move.to, entity_move.from) } else { (entity_move.from, entity_move.to) };
+
+      if let Some(position) = positions.get_mut(entities.entity(entity_move.id)) {
+        position.x = to.0;
+        position.y = to.1;
+      }
+
+      event_queue.events.push(Event::EntityMoved(EntityMoved { id: entity_move.id }));
+      if entity_move.teleported {
+        event_queue.events.push(Event::Teleported(Teleported { id: entity_move.id, from, to }));
+      }
+    }
+  }
+
+  // Takes a key or an opened door off the board
+  fn apply_effect(effect: &Effect, entities: &Entities, positions: &mut WriteStorage<Position>, keyring: &mut Keyring, event_queue: &mut EventQueue) {
+    match *effect {
+      Effect::KeyTaken { id, color, .. } => {
+        positions.remove(entities.entity(id));
+        keyring.keys.push(color);
+        event_queue.events.push(Event::KeyPickedUp(KeyPickedUp { id, color }));
+      }
+      Effect::DoorOpened { id, color, .. } => {
+        positions.remove(entities.entity(id));
+        keyring.take(color);
+        event_queue.events.push(Event::DoorUnlocked(DoorUnlocked { id, color }));
+      }
+    }
+  }
+
+  // Puts a key or a door back where it was, locked again
+  fn undo_effect(effect: &Effect, entities: &Entities, positions: &mut WriteStorage<Position>, keyring: &mut Keyring) {
+    match *effect {
+      Effect::KeyTaken { id, position, color } => {
+        positions.insert(entities.entity(id), position).expect("expected key position");
+        keyring.take(color);
+      }
+      Effect::DoorOpened { id, position, color } => {
+        positions.insert(entities.entity(id), position).expect("expected door position");
+        keyring.keys.push(color);
+      }
+    }
+  }
+
+  // Where each of the moving entities ends up. They all step one tile, then
+  // the ones on ice keep sliding until something is in the way, and the ones
+  // stopping on a teleporter go through it when its exit is free. Front ones
+  // are moved first so the ones behind stop against them.
+  fn destinations(starts: &[(i32, i32)], (dx, dy): (i32, i32), occupied: &mut HashSet<(i32, i32)>, tiles: &Tiles, map_bounds: &MapBounds) -> Vec<Destination> {
+    let mut destinations: Vec<Destination> = starts
+      .iter()
+      .map(|(x, y)| Destination { position: (x + dx, y + dy), teleported: false })
+      .collect();
+    for start in starts {
+      occupied.remove(start);
+    }
+    occupied.extend(destinations.iter().map(|destination| destination.position));
+
+    let mut front_first: Vec<usize> = (0..destinations.len()).collect();
+    front_first.sort_by_key(|index| {
+      let (x, y) = destinations[*index].position;
+      -(x * dx + y * dy)
+    });
+
+    for index in front_first {
+      let destination = &mut destinations[index];
+      loop {
+        let (x, y) = destination.position;
+        let next = (x + dx, y + dy);
+        if !tiles.ice.contains(&destination.position) || occupied.contains(&next) || !map_bounds.contains(next.0, next.1) {
+          break;
+        }
+        occupied.remove(&destination.position);
+        occupied.insert(next);
+        destination.position = next;
+      }
+
+      if let Some(exit) = tiles.exits.get(&destination.position) {
+        if !occupied.contains(exit) {
+          occupied.remove(&destination.position);
+          occupied.insert(*exit);
+          destination.position = *exit;
+          destination.teleported = true;
+        }
+      }
+    }
+
+    destinations
+  }
+
+  // Turns the player the way it moved, walking or pushing depending on the step
+  fn take_step(players: &mut WriteStorage<Player>, record: &MoveRecord, now: Duration) {
+    let step = if record.is_push() { PlayerState::Pushing } else { PlayerState::Walking };
+    for player in players.join() {
+      player.take_step(record.direction, step, now);
+    }
+  }
+}
+
 impl<'a> System<'a> for InputSystem {
     // Data
   type SystemData = (
     Write<'a, EventQueue>,
     Write<'a, InputQueue>,
     Write<'a, Gameplay>,
+    Write<'a, MoveHistory>,
+    Write<'a, CurrentHint>,
+    Read<'a, MapBounds>,
+    Read<'a, Time>,
+    Read<'a, Animation>,
+    Read<'a, RuleSet>,
+    Write<'a, Keyring>,
     Entities<'a>,
     WriteStorage<'a, Position>,
-    ReadStorage<'a, Player>,
+    WriteStorage<'a, Player>,
     ReadStorage<'a, Movable>,
     ReadStorage<'a, Immovable>,
+    ReadStorage<'a, Ice>,
+    ReadStorage<'a, Teleporter>,
+    ReadStorage<'a, Key>,
+    ReadStorage<'a, Door>,
   );
 
   fn run(&mut self, data: Self::SystemData) {
-    let (mut event_queue, mut input_queue, mut gameplay, entities, mut positions, players, movables, immovables) = data;
+    let (mut event_queue, mut input_queue, mut gameplay, mut history, mut current_hint, map_bounds, time, animation, rules, mut keyring, entities, mut positions, mut players, movables, immovables, ices, teleporters, keys, doors) = data;
+
+    // Leave the commands queued until the last step is done animating
+    if animation.in_progress && animation.settings.input == AnimationInput::Buffer {
+      return;
+    }
+
+    let command = match input_queue.pop(time.delta) {
+      Some(command) => command,
+      None => return,
+    };
+
+    // Any step makes the hint out of date
+    current_hint.hint = None;
+
+    let (direction, pulling) = match command {
+      Command::Move(direction) => (direction, false),
+      Command::Pull(direction) => (direction, rules.pull),
+      Command::Undo => {
+        if let Some(record) = history.done.pop() {
+          InputSystem::replay_record(&record, true, &entities, &mut positions, &mut keyring, &mut event_queue);
+          InputSystem::take_step(&mut players, &record, time.delta);
+          gameplay.moves_count -= 1;
+          if record.is_push() {
+            gameplay.pushes_count -= 1;
+          }
+          history.undone.push(record);
+        }
+        return;
+      }
+      Command::Redo => {
+        if let Some(record) = history.undone.pop() {
+          InputSystem::replay_record(&record, false, &entities, &mut positions, &mut keyring, &mut event_queue);
+          InputSystem::take_step(&mut players, &record, time.delta);
+          gameplay.moves_count += 1;
+          if record.is_push() {
+            gameplay.pushes_count += 1;
+          }
+          history.done.push(record);
+        }
+        return;
+      }
+    };
+
+    let (dx, dy) = direction.delta();
+    let player_position = (&positions, &players).join().map(|(position, _)| (position.x, position.y)).next();
+    let mut effects = Vec::new();
+
+    // A locked door ahead opens when the player has a key of its color, and
+    // the player steps right through it
+    if let Some((x, y)) = player_position {
+      let door = (&entities, &positions, &doors)
+        .join()
+        .find(|(_, position, _)| (position.x, position.y) == (x + dx, y + dy))
+        .map(|(entity, position, door)| (entity.id(), *position, door.color));
+
+      if let Some((id, position, color)) = door {
+        if keyring.keys.contains(&color) {
+          effects.push(Effect::DoorOpened { id, position, color });
+        }
+      }
+    }
+    for effect in effects.iter() {
+      InputSystem::apply_effect(effect, &entities, &mut positions, &mut keyring, &mut event_queue);
+    }
 
     let mut to_move = Vec::new();
 
     for (position , _player) in (&positions, &players).join() {
-      if let Some(key) = input_queue.keys_pressed.pop() {
-        let mov: HashMap<(u8, u8), Index> = (&entities, &movables, &positions)
-                    .join()
-                    .map(|t| ((t.2.x, t.2.y), t.0.id()))
-                    .collect::<HashMap<_, _>>();
-        let immov = (&entities, &immovables, &positions)
-                    .join()
-                    .map(|t| ((t.2.x, t.2.y), t.0.id()))
-                    .collect::<HashMap<_, _>>();
-
-        let (start, end , is_x) = match key {
-          KeyCode::Up => (position.y, 0, false),
-          KeyCode::Down => (position.y, MAP_HEIGHT, false),
-          KeyCode::Left => (position.x, 0, true),
-          KeyCode::Right => (position.x, MAP_WIDTH, true),
-          _ => continue, 
-        };
-
-        let range = if start < end {
-          (start..=end).collect::<Vec<_>>()
-        } else {
-          (end..=start).rev().collect::<Vec<_>>()
-        };
-
-        for x_or_y in range {
-          let pos = if is_x {
-            (x_or_y, position.y)
-          } else {
-            (position.x, x_or_y)
-          };
-
-          // find a movable
-          // if it exists, we try to move it and continue
-          // if it doesn't exist, we continue and try to find an immovable instead
-          match mov.get(&pos) {
-            Some(id) => to_move.push((key, id.clone())),
-            None => {
-                // find an immovable
-                // if it exists, we need to stop and not move anything
-                // if it doesn't exist, we stop because we found a gap
-              match immov.get(&pos) {
-                Some(_id) =>{
-                  to_move.clear();
-                  event_queue.events.push(Event::PlayerHitObstacle);
-                },
-                None => break,
-              }
+      let mov: HashMap<(i32, i32), Index> = (&entities, &movables, &positions)
+                  .join()
+                  .map(|t| ((t.2.x, t.2.y), t.0.id()))
+                  .collect::<HashMap<_, _>>();
+      let immov = (&entities, &immovables, &positions)
+                  .join()
+                  .map(|t| ((t.2.x, t.2.y), t.0.id()))
+                  .collect::<HashMap<_, _>>();
+
+      // Pulling only takes a free tile ahead, and drags along the box
+      // behind the player if there is one
+      if pulling {
+        let ahead = (position.x + dx, position.y + dy);
+        if mov.contains_key(&ahead) || immov.contains_key(&ahead) {
+          event_queue.events.push(Event::PlayerHitObstacle);
+        } else if map_bounds.contains(ahead.0, ahead.1) {
+          to_move.extend(mov.get(&(position.x, position.y)));
+          to_move.extend(mov.get(&(position.x - dx, position.y - dy)));
+        }
+        continue;
+      }
+
+      // Walk from the player towards the edge of the map in the direction
+      // of the key, collecting everything that will have to move.
+      let mut pos = (position.x, position.y);
+      while map_bounds.contains(pos.0, pos.1) {
+        // find a movable
+        // if it exists, we try to move it and continue
+        // if it doesn't exist, we continue and try to find an immovable instead
+        match mov.get(&pos) {
+          Some(id) => to_move.push(*id),
+          None => {
+              // find an immovable
+              // if it exists, we need to stop and not move anything
+              // if it doesn't exist, we stop because we found a gap
+            match immov.get(&pos) {
+              Some(_id) =>{
+                to_move.clear();
+                event_queue.events.push(Event::PlayerHitObstacle);
+                break;
+              },
+              None => break,
             }
           }
         }
+
+        pos = (pos.0 + dx, pos.1 + dy);
+      }
+
+      // There is no gap past the edge of the map, so nothing can move
+      if !map_bounds.contains(pos.0, pos.1) {
+        to_move.clear();
+      }
+
+      // Everything but the player is a box being pushed, and the level's
+      // rules say how many of them can go at once
+      let pushed = to_move.len().saturating_sub(1);
+      if rules.push.max_boxes().is_some_and(|max_boxes| pushed > max_boxes) {
+        to_move.clear();
+        event_queue.events.push(Event::PlayerHitObstacle);
       }
     }
 
-    if to_move.len() > 0 {
-      gameplay.moves_count += 1;
+    // The player turns towards the key even when it can't move
+    for player in (&mut players).join() {
+      player.facing = direction;
     }
 
-    for (key, id) in to_move {
-      let position = positions.get_mut(entities.entity(id));
-      
-      if let Some(position) = position {
-        match key {
-          KeyCode::Up => position.y -= 1,
-          KeyCode::Down => position.y += 1,
-          KeyCode::Left => position.x -= 1,
-          KeyCode::Right => position.x += 1,
-          _ => (),
-        }
-      }
+    if to_move.is_empty() {
+      return;
+    }
+
+    let mut occupied: HashSet<(i32, i32)> = (&positions, &movables).join().map(|(position, _)| (position.x, position.y)).collect();
+    occupied.extend((&positions, &immovables).join().map(|(position, _)| (position.x, position.y)));
+    let mut channels: HashMap<u8, Vec<(i32, i32)>> = HashMap::new();
+    for (position, teleporter) in (&positions, &teleporters).join() {
+      channels.entry(teleporter.channel).or_default().push((position.x, position.y));
+    }
+    let tiles = Tiles {
+      ice: (&positions, &ices).join().map(|(position, _)| (position.x, position.y)).collect(),
+      exits: channels
+        .values()
+        .filter_map(|pads| match pads[..] {
+          [first, second] => Some([(first, second), (second, first)]),
+          _ => None,
+        })
+        .flatten()
+        .collect(),
+    };
 
+    let to_move: Vec<(Index, (i32, i32))> = to_move
+      .into_iter()
+      .filter_map(|id| positions.get(entities.entity(id)).map(|position| (id, (position.x, position.y))))
+      .collect();
+    let starts: Vec<(i32, i32)> = to_move.iter().map(|(_, start)| *start).collect();
+    let destinations = InputSystem::destinations(&starts, direction.delta(), &mut occupied, &tiles, &map_bounds);
+
+    // However far they slid, every entity moves once and fires a single event
+    let mut record = MoveRecord { direction, moves: Vec::new(), effects: Vec::new(), pulled: false };
+    for ((id, from), Destination { position: to, teleported }) in to_move.into_iter().zip(destinations) {
+      if let Some(position) = positions.get_mut(entities.entity(id)) {
+        position.x = to.0;
+        position.y = to.1;
+      }
+      record.moves.push(EntityMove { id, from, to, teleported });
       event_queue.events.push(Event::EntityMoved(EntityMoved { id }));
+      if teleported {
+        event_queue.events.push(Event::Teleported(Teleported { id, from, to }));
+      }
     }
+
+    // The player picks up the keys wherever it ends up. Keys lie on plain
+    // floor, where every slide stops, so none are ever slid past.
+    if let Some((player, _)) = (&positions, &players).join().next() {
+      let taken: Vec<Effect> = (&entities, &positions, &keys)
+        .join()
+        .filter(|(_, position, _)| (position.x, position.y) == (player.x, player.y))
+        .map(|(entity, position, key)| Effect::KeyTaken { id: entity.id(), position: *position, color: key.color })
+        .collect();
+      for effect in taken.iter() {
+        InputSystem::apply_effect(effect, &entities, &mut positions, &mut keyring, &mut event_queue);
+      }
+      effects.extend(taken);
+    }
+    record.effects = effects;
+    record.pulled = pulling && record.is_push();
+
+    gameplay.moves_count += 1;
+    if record.is_push() {
+      gameplay.pushes_count += 1;
+    }
+    InputSystem::take_step(&mut players, &record, time.delta);
+
+    // A new move starts a new branch of history, so nothing is left to redo
+    history.done.push(record);
+    history.undone.clear();
   }
-}
\ No newline at end of file
+}
@@ -1,4 +1,8 @@
 pub mod input_system;
 pub mod render_system;
 pub mod game_play_state_system;
-pub mod event_system;
\ No newline at end of file
+pub mod event_system;
+pub mod replay_system;
+pub mod repeat_system;
+pub mod animation_system;
+pub mod gate_system;
\ No newline at end of file
@@ -1,18 +1,36 @@
-use std::{collections::HashMap, time::Duration};
+use std::{collections::BTreeMap, time::Duration};
 
-use ggez::{glam::Vec2, graphics::{self, Canvas, Color, DrawParam, Drawable, Image, InstanceArray}, Context};
-use specs::{Join, Read, ReadStorage, System};
+use ggez::{glam::Vec2, graphics::{self, Canvas, Color, DrawMode, DrawParam, Drawable, InstanceArray, Mesh, Rect}, Context};
+use specs::{Entities, Join, Read, ReadStorage, System};
 
-use crate::{components::{Position, Renderable, RenderableKind}, constants::TILE_WIDTH, resources::{Gameplay, Time}};
+use crate::{camera::Camera, components::{Player, PlayerState, Position, Renderable, RenderableKind, Stuck, VisualPosition}, constants::TILE_WIDTH, level_pack::LevelPack, textures::TextureStore, resources::{BoxSelection, CurrentHint, CurrentLevel, Gameplay, Hint, Keyring, Time}};
+
+// The player's frames depend on where it faces and what it is doing, e.g.
+// /images/player_left_walk_1.png. Idle frames change every 250ms like other
+// animations, walking and pushing ones twice as fast.
+fn player_image_path(player: &Player, delta: Duration) -> String {
+  let state = player.state(delta);
+  let (frames, frame_millis) = match state {
+    PlayerState::Idle => (3, 250),
+    PlayerState::Walking | PlayerState::Pushing => (2, 125),
+  };
+  let frame = (delta.as_millis() / frame_millis) % frames + 1;
+
+  format!("/images/player_{}_{}_{}.png", player.facing, state, frame)
+}
 
 pub struct RenderSystem<'a>{
-  context: &'a mut Context
+  context: &'a mut Context,
+  texture_store: &'a mut TextureStore,
+  camera: &'a Camera,
 }
 
 impl<'a> RenderSystem<'a> {
-  pub fn new(context: &'a mut Context) -> Self {
+  pub fn new(context: &'a mut Context, texture_store: &'a mut TextureStore, camera: &'a Camera) -> Self {
     Self {
-      context
+      context,
+      texture_store,
+      camera,
     }
   }
 
@@ -25,7 +43,7 @@ impl<'a> RenderSystem<'a> {
     text.draw(canvas, draw_params);
   }  
 
-  pub fn get_image(&mut self, renderable: &Renderable, delta: Duration) -> Image {
+  pub fn get_image_path (&self, renderable: &Renderable, delta: Duration) -> String {
     let path_index = match renderable.kind() {
         RenderableKind::Static => {
           // We only have one image, so we just return that
@@ -41,37 +59,36 @@ impl<'a> RenderSystem<'a> {
         }
     };
 
-    let image_path = renderable.path(path_index);
-    Image::from_path(self.context, image_path).expect("expected image")
+    renderable.path(path_index)
   }
 
-  #[allow(dead_code)]
-  pub fn get_image_path (&self, renderable: &Renderable, delta: Duration) -> String {
-    let path_index = match renderable.kind() {
-        RenderableKind::Static => {
-          // We only have one image, so we just return that
-          0
-        }
-        RenderableKind::Animated => {
-          // If we have multiple, we want to select the right one based on the delta time.
-          // First we get the delta in milliseconds, we % by 1000 to get the milliseconds
-          // only and finally we divide by 250 to get a number between 0 and 4. If it's 4
-          // we technically are on the next iteration of the loop (or on 0), but we will let
-          // the renderable handle this logic of wrapping frames.
-          ((delta.as_millis() % 1000) / 250) as usize
-        }
+  // Highlights the tile the hinted step goes to, in orange when it pushes a box
+  pub fn draw_hint(&mut self, canvas: &mut Canvas, hint: &Hint, player: &Position) {
+    let (dx, dy) = hint.direction.delta();
+    let x = (player.x + dx) as f32 * TILE_WIDTH;
+    let y = (player.y + dy) as f32 * TILE_WIDTH;
+    let color = match hint.pushed_box {
+      Some(_) => Color::new(1.0, 0.5, 0.0, 0.5),
+      None => Color::new(0.0, 0.8, 0.2, 0.5),
     };
 
-    renderable.path(path_index)
+    let rect = Rect::new(x, y, TILE_WIDTH, TILE_WIDTH);
+    let mesh = Mesh::new_rectangle(self.context, DrawMode::fill(), rect, color).expect("expected hint mesh");
+    canvas.draw(&mesh, DrawParam::new());
   }
 
-  pub fn draw_images_batch(&mut self, canvas: &mut Canvas, image_path: &str, draw_params: &Vec<DrawParam>) {
-    let image = Image::from_path(self.context, image_path).expect("expected image");
-    let mut instance_array = InstanceArray::new_ordered(self.context, image);            
-      
-    for draw_param in draw_params.iter() {        
-      instance_array.push(*draw_param);
-    }
+  // Outlines the box picked with the mouse
+  pub fn draw_selection(&mut self, canvas: &mut Canvas, position: &Position) {
+    let rect = Rect::new(position.x as f32 * TILE_WIDTH, position.y as f32 * TILE_WIDTH, TILE_WIDTH, TILE_WIDTH);
+    let color = Color::new(0.1, 0.4, 1.0, 1.0);
+    let mesh = Mesh::new_rectangle(self.context, DrawMode::stroke(3.0), rect, color).expect("expected selection mesh");
+    canvas.draw(&mesh, DrawParam::new());
+  }
+
+  pub fn draw_images_batch(&mut self, canvas: &mut Canvas, image_path: &str, draw_params: &[DrawParam]) {
+    let image = self.texture_store.image(self.context, image_path);
+    let mut instance_array = InstanceArray::new(self.context, image);
+    instance_array.set(draw_params.iter().copied());
 
     instance_array.draw(canvas, DrawParam::new());
   }
@@ -81,45 +98,98 @@ impl<'a> System<'a> for RenderSystem<'a> {
   type SystemData = (
     Read<'a, Gameplay>, 
     Read<'a, Time>,
+    Read<'a, LevelPack>,
+    Read<'a, CurrentLevel>,
+    Read<'a, CurrentHint>,
+    Read<'a, BoxSelection>,
+    Read<'a, Keyring>,
+    Entities<'a>,
     ReadStorage<'a, Position>, 
-    ReadStorage<'a, Renderable>
+    ReadStorage<'a, Renderable>,
+    ReadStorage<'a, Player>,
+    ReadStorage<'a, Stuck>,
+    ReadStorage<'a, VisualPosition>,
   );
 
   fn run(&mut self, data: Self::SystemData) {
-    let (gameplay, time, positions, renderables) = data;
+    let (gameplay, time, level_pack, current_level, current_hint, box_selection, keyring, entities, positions, renderables, players, stuck, visual_positions) = data;
 
     // Clearing the screen (this gives us the background colour)
     let mut canvas = graphics::Canvas::from_frame(self.context, Color::WHITE);
 
     let canvas_mut = &mut canvas;
 
-    // Get all the renderables with their positions and sort by the position z
-    // This will allow us to have entities layered visually.
-    let mut rendering_data = (&positions, &renderables).join().collect::<Vec<_>>();
-    rendering_data.sort_by_key(|&k| k.0.z);
-
-    let rendering_batches: HashMap<String, Vec<DrawParam>> = HashMap::new();
+    // The board is drawn in world coordinates, scaled and scrolled by the camera
+    let (world_x, world_y, world_width, world_height) = self.camera.world_rect();
+    canvas_mut.set_screen_coordinates(Rect::new(world_x, world_y, world_width, world_height));
+
+    // Group everything to draw by z, so entities are layered visually, then
+    // by image, so each image is drawn once per layer as a single batch.
+    let mut rendering_batches: BTreeMap<(u8, String), Vec<DrawParam>> = BTreeMap::new();
+
+    // The player has no `Renderable`, its frames come from the `Player`
+    for (position, renderable, stuck, visual_position, player) in (&positions, renderables.maybe(), stuck.maybe(), visual_positions.maybe(), players.maybe()).join() {
+        let image_path = match (player, renderable) {
+          (Some(player), _) => player_image_path(player, time.delta),
+          (None, Some(renderable)) => self.get_image_path(renderable, time.delta),
+          (None, None) => continue,
+        };
+        // Moving entities are drawn where their animation is at
+        let (tile_x, tile_y) = match visual_position {
+          Some(visual_position) => (visual_position.x, visual_position.y),
+          None => (position.x as f32, position.y as f32),
+        };
+        let x = tile_x * TILE_WIDTH;
+        let y = tile_y * TILE_WIDTH;
+
+        // Boxes that can't be solved any more are tinted red
+        let color = if stuck.is_some() { Color::new(1.0, 0.4, 0.4, 1.0) } else { Color::WHITE };
+        let draw_param = DrawParam::new().dest(Vec2::new(x, y)).color(color);
+        rendering_batches.entry((position.z, image_path)).or_default().push(draw_param);
+    }
 
-    // Iterate through all pairs of positions & renderables, load the image
-    // and draw it at the specified position.
-    for (position, renderable) in rendering_data.iter() {
-        // Load the image
-        let image = self.get_image(renderable, time.delta);
-        let x = position.x as f32 * TILE_WIDTH;
-        let y = position.y as f32 * TILE_WIDTH;
+    // Draw all the batches in the correct order
+    for ((_z, image_path), draw_params) in rendering_batches.iter() {
+      self.draw_images_batch(canvas_mut, image_path, draw_params);
+    }
 
-        image.draw(canvas_mut, DrawParam::new().dest(Vec2::new(x, y)));
+    if let Some(hint) = current_hint.hint.as_ref() {
+      if let Some((player, _)) = (&positions, &players).join().next() {
+        self.draw_hint(canvas_mut, hint, player);
+      }
     }
 
-    // Draw all the batches in the correct order    
-    for (image_path, draw_params) in rendering_batches.iter() {
-      self.draw_images_batch(canvas_mut, image_path, draw_params);
+    if let Some(position) = box_selection.selected.and_then(|id| positions.get(entities.entity(id))) {
+      self.draw_selection(canvas_mut, position);
     }
 
-    self.draw_text(canvas_mut, &gameplay.state.to_string(), 525.0, 80.0);
-    self.draw_text(canvas_mut, &gameplay.moves_count.to_string(), 525.0, 100.0);
+    // The HUD is drawn in window pixels, next to the board, at any zoom
+    let (window_width, window_height) = self.context.gfx.drawable_size();
+    canvas_mut.set_screen_coordinates(Rect::new(0.0, 0.0, window_width, window_height));
+
+    let (hud_x, hud_y) = self.camera.hud_position();
+    let level_text = format!("Level {}/{}", current_level.index + 1, level_pack.len());
+    self.draw_text(canvas_mut, &level_text, hud_x, hud_y);
+    if let Some(title) = level_pack.level(current_level.index).and_then(|level| level.metadata.title.as_ref()) {
+      self.draw_text(canvas_mut, title, hud_x, hud_y + 20.0);
+    }
+    self.draw_text(canvas_mut, &gameplay.state.to_string(), hud_x, hud_y + 40.0);
+    let moves = format!("Moves: {}", gameplay.moves_count);
+    self.draw_text(canvas_mut, &moves, hud_x, hud_y + 60.0);
+    let pushes = format!("Pushes: {}", gameplay.pushes_count);
+    self.draw_text(canvas_mut, &pushes, hud_x, hud_y + 80.0);
     let fps = format!("FPS: {:.0}", self.context.time.fps());
-    self.draw_text(canvas_mut, &fps, 525.0, 120.0);
+    self.draw_text(canvas_mut, &fps, hud_x, hud_y + 100.0);
+    if let Some(hint) = current_hint.hint.as_ref() {
+      let action = if hint.pushed_box.is_some() { "push" } else { "walk" };
+      let hint_text = format!("Hint: {} {:?}", action, hint.direction);
+      self.draw_text(canvas_mut, &hint_text, hud_x, hud_y + 120.0);
+    }
+    if !keyring.keys.is_empty() {
+      let keys: Vec<String> = keyring.keys.iter().map(|key| key.to_string()).collect();
+      let keys_text = format!("Keys: {}", keys.join(", "));
+      self.draw_text(canvas_mut, &keys_text, hud_x, hud_y + 140.0);
+    }
         
 
     // Finally, present the context, this will actually display everything
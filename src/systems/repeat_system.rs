@@ -0,0 +1,31 @@
+use specs::{Read, System, Write};
+
+use crate::resources::{InputQueue, KeyRepeat, Time};
+
+pub struct RepeatSystem {}
+
+impl<'a> System<'a> for RepeatSystem {
+  type SystemData = (
+    Write<'a, KeyRepeat>,
+    Write<'a, InputQueue>,
+    Read<'a, Time>,
+  );
+
+  fn run(&mut self, data: Self::SystemData) {
+    let (mut key_repeat, mut input_queue, time) = data;
+
+    let command = match key_repeat.held {
+      Some(command) => command,
+      None => return,
+    };
+
+    // Only repeat once the last step is handled, so holding a key never
+    // queues up more steps than the game can play
+    if time.delta < key_repeat.next_repeat_at || !input_queue.commands.is_empty() {
+      return;
+    }
+
+    input_queue.push_expiring(command, time.delta);
+    key_repeat.next_repeat_at = time.delta + key_repeat.settings.interval;
+  }
+}
@@ -0,0 +1,45 @@
+use specs::{Read, System, Write};
+
+use crate::{events::{Event, ReplayFinished}, resources::{EventQueue, Gameplay, GameplayState, InputQueue, Replay, Time}};
+
+pub struct ReplaySystem {}
+
+impl<'a> System<'a> for ReplaySystem {
+  type SystemData = (
+    Write<'a, Replay>,
+    Write<'a, InputQueue>,
+    Write<'a, EventQueue>,
+    Read<'a, Gameplay>,
+    Read<'a, Time>,
+  );
+
+  fn run(&mut self, data: Self::SystemData) {
+    let (mut replay, mut input_queue, mut event_queue, gameplay, time) = data;
+
+    if !replay.active {
+      return;
+    }
+
+    if gameplay.state == GameplayState::Won {
+      replay.active = false;
+      event_queue.events.push(Event::ReplayFinished(ReplayFinished { solved: true }));
+      return;
+    }
+
+    // Wait for the previous step to be handled before sending the next one
+    if time.delta < replay.next_step_at || !input_queue.commands.is_empty() {
+      return;
+    }
+
+    match replay.steps.pop_front() {
+      Some(command) => {
+        input_queue.push(command);
+        replay.next_step_at = time.delta + replay.interval;
+      }
+      None => {
+        replay.active = false;
+        event_queue.events.push(Event::ReplayFinished(ReplayFinished { solved: false }));
+      }
+    }
+  }
+}
@@ -0,0 +1,34 @@
+use std::collections::HashMap;
+
+use ggez::{graphics::Image, Context};
+
+#[derive(Default)]
+pub struct TextureStore {
+  pub images: HashMap<String, Image>,
+}
+
+impl TextureStore {
+  // Images that weren't loaded up front are loaded the first time they're
+  // asked for, then kept like the others
+  pub fn image(&mut self, context: &mut Context, image_path: &str) -> Image {
+    self.images
+      .entry(image_path.to_string())
+      .or_insert_with(|| Image::from_path(context, image_path).expect("expected image"))
+      .clone()
+  }
+}
+
+pub fn initialize_textures(texture_store: &mut TextureStore, context: &mut Context) {
+  let image_paths = context.fs.read_dir("/images").expect("expected images directory");
+
+  for image_path in image_paths {
+    if image_path.extension().and_then(|extension| extension.to_str()) != Some("png") {
+      continue;
+    }
+
+    let image_path = image_path.to_string_lossy().replace('\\', "/");
+    let image = Image::from_path(context, &image_path).expect("expected image loaded");
+
+    texture_store.images.insert(image_path, image);
+  }
+}